@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use regex::Regex;
+
+use shell;
+use tools;
+use types::Tokens;
+
+/// It writes to stdout directly, so that the listing can be redirected,
+/// e.g. `alias > aliases.sh`.
+fn print_alias(flag: &str, name: &str, value: &str) {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    let _ = writeln!(handle, "alias {}{}={}", flag, name, tools::quote_word(value));
+}
+
+fn list_alias(flag: &str, alias: &HashMap<String, String>) {
+    let mut names: Vec<&String> = alias.keys().collect();
+    names.sort();
+    for name in names {
+        print_alias(flag, name, &alias[name]);
+    }
+}
+
+/// The alias builtin. Values in single quotes like `'echo $HOME'` are
+/// kept as they are, and get expanded when the alias is used.
+///
+/// - `alias name=value`: regular aliases, for command words
+/// - `alias -g name=value`: global aliases, for words in any position
+/// - `alias -s ext=value`: suffix aliases, run `value file.ext` for
+///   command words like `file.ext`
+pub fn run(sh: &mut shell::Shell, tokens: &Tokens) -> i32 {
+    let mut args = &tokens[1..];
+    let mut flag = "";
+    if !args.is_empty() && (args[0].1 == "-g" || args[0].1 == "-s" || args[0].1 == "-p") {
+        flag = if args[0].1 == "-g" {
+            "-g "
+        } else if args[0].1 == "-s" {
+            "-s "
+        } else {
+            ""
+        };
+        args = &args[1..];
+    }
+
+    if args.is_empty() {
+        if flag.is_empty() {
+            list_alias("", &sh.alias);
+        }
+        if flag.is_empty() || flag == "-g " {
+            list_alias("-g ", &sh.alias_global);
+        }
+        if flag.is_empty() || flag == "-s " {
+            list_alias("-s ", &sh.alias_suffix);
+        }
+        return 0;
+    }
+
+    let re = match Regex::new(r"^([a-zA-Z0-9_\.-]+)=(.*)$") {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: Regex error: {:?}", e);
+            return 1;
+        }
+    };
+
+    let mut status = 0;
+    for (sep, text) in args {
+        if !sep.is_empty() || !re.is_match(text) {
+            let value = if flag == "-s " {
+                sh.alias_suffix.get(text)
+            } else if flag == "-g " {
+                sh.alias_global.get(text)
+            } else {
+                sh.alias.get(text)
+            };
+            match value {
+                Some(value) => print_alias(flag, text, value),
+                None => {
+                    println_stderr!("cicada: alias: {}: not found", text);
+                    status = 1;
+                }
+            }
+            continue;
+        }
+        for cap in re.captures_iter(text) {
+            let value = tools::unquote(&cap[2]);
+            if flag == "-g " {
+                sh.add_alias_global(&cap[1], &value);
+            } else if flag == "-s " {
+                sh.add_alias_suffix(&cap[1], &value);
+            } else {
+                sh.add_alias(&cap[1], &value);
+            }
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use execute::run_procs;
+    use shell::Shell;
+
+    #[test]
+    fn test_alias_redirected() {
+        let dir = env::temp_dir();
+        let out = dir.join("cicada-test-alias.txt");
+        let out = out.to_string_lossy();
+        let mut sh = Shell::new();
+        assert_eq!(run_procs(&mut sh, "alias e='echo $HOME' ll='ls -l'", false), 0);
+        assert_eq!(sh.alias.get("e"), Some(&"echo $HOME".to_string()));
+
+        let line = format!("alias > {}", out);
+        assert_eq!(run_procs(&mut sh, &line, false), 0);
+        let text = fs::read_to_string(&*out).unwrap();
+        assert_eq!(text, "alias e='echo $HOME'\nalias ll='ls -l'\n");
+
+        let line = format!("alias | head -1 > {}", out);
+        assert_eq!(run_procs(&mut sh, &line, false), 0);
+        let text = fs::read_to_string(&*out).unwrap();
+        assert_eq!(text, "alias e='echo $HOME'\n");
+        let _ = fs::remove_file(&*out);
+    }
+
+    #[test]
+    fn test_alias_expanded() {
+        let mut sh = Shell::new();
+        let line = "for i in 1 2; do alias n$i=v$i; done";
+        assert_eq!(run_procs(&mut sh, line, false), 0);
+        assert_eq!(sh.alias.get("n1"), Some(&"v1".to_string()));
+        assert_eq!(sh.alias.get("n2"), Some(&"v2".to_string()));
+    }
+}
@@ -1,28 +1,29 @@
 use parsers;
+use scripting;
 use shell;
 use std::env;
-use std::error::Error;
+use std::io::Write;
 use std::path::PathBuf;
 use tools;
 
 pub fn run(sh: &mut shell::Shell, tokens: &Vec<(String, String)>) -> i32 {
     let args = parsers::parser_line::tokens_to_args(&tokens);
     if args.len() > 2 {
-        println!("invalid cd command");
+        println_stderr!("cicada: cd: too many arguments");
         return 1;
     }
     let mut current_dir = PathBuf::new();
     match env::current_dir() {
         Ok(x) => current_dir = x,
         Err(e) => {
-            println!("current_dir() failed: {}", e.description());
+            println_stderr!("cicada: cd: cannot get current dir: {}", e);
         }
     }
     let mut str_current_dir = "";
     match current_dir.to_str() {
         Some(x) => str_current_dir = x,
         None => {
-            println!("current_dir to str failed.");
+            println_stderr!("cicada: cd: current dir is not valid UTF-8");
         }
     }
     let mut dir_to = if args.len() == 1 {
@@ -33,22 +34,47 @@ pub fn run(sh: &mut shell::Shell, tokens: &Vec<(String, String)>) -> i32 {
     };
 
     if dir_to == "-" {
-        if sh.previous_dir == "" {
-            println!("no previous dir");
+        match sh.get_env("OLDPWD") {
+            Some(x) => dir_to = x,
+            None => {
+                println_stderr!("cicada: cd: no previous dir");
+                return 0;
+            }
+        }
+        if dir_to.is_empty() {
+            println_stderr!("cicada: cd: no previous dir");
             return 0;
         }
-        dir_to = sh.previous_dir.clone();
-    } else if !dir_to.starts_with('/') {
-        dir_to = format!("{}/{}", str_current_dir, dir_to);
     }
-    if str_current_dir != dir_to {
-        sh.previous_dir = str_current_dir.to_string();
+    let dir_arg = dir_to.clone();
+    if !dir_to.starts_with('/') {
+        dir_to = format!("{}/{}", str_current_dir, dir_to);
     }
     match env::set_current_dir(&dir_to) {
-        Ok(_) => 0,
+        Ok(_) => {
+            update_pwd(sh, str_current_dir);
+            0
+        }
         Err(e) => {
-            println!("cd: {}", e.description());
+            println_stderr!("cicada: cd: {}: {}", dir_arg, e);
             1
         }
     }
 }
+
+/// Keep `$PWD` and `$OLDPWD` up to date after changing directory.
+fn update_pwd(sh: &mut shell::Shell, old_dir: &str) {
+    if let Ok(x) = env::current_dir() {
+        let new_dir = x.to_string_lossy().to_string();
+        if new_dir == old_dir {
+            return;
+        }
+        if let Err(e) = sh.set_env("OLDPWD", old_dir) {
+            println_stderr!("cicada: cd: {}", e);
+        }
+        if let Err(e) = sh.set_env("PWD", &new_dir) {
+            println_stderr!("cicada: cd: {}", e);
+        }
+        scripting::run_hook(sh, "chpwd", Vec::new());
+    }
+}
@@ -2,7 +2,10 @@
 use std::io::Write;
 use std::process;
 
-pub fn run(tokens: &Vec<(String, String)>) -> i32 {
+use scripting;
+use shell;
+
+pub fn run(sh: &mut shell::Shell, tokens: &Vec<(String, String)>) -> i32 {
     if tokens.len() > 2 {
         println_stderr!("cicada: exit: too many arguments");
         return 1;
@@ -21,6 +24,7 @@ pub fn run(tokens: &Vec<(String, String)>) -> i32 {
             }
         }
     }
+    scripting::run_hook(sh, "on_exit", Vec::new());
     process::exit(code);
     0
 }
@@ -1,51 +1,170 @@
-use regex::Regex;
 use std::env;
-use std::io::Write;
+use std::io::{self, Write};
+
+use regex::Regex;
 
-use parsers;
 use shell;
 use tools;
 
-pub fn run(sh: &shell::Shell, line: &str) -> i32 {
-    if !tools::is_env(line) {
-        println!("export: invalid command");
-        println!("usage: export XXX=YYY");
-        return 1;
+/// List exported variables in the form that can be read back by cicada.
+/// It writes to stdout directly, so that the listing can be redirected,
+/// e.g. `export -p > vars.sh`.
+fn list_exported() -> i32 {
+    let mut vars: Vec<(String, String)> = env::vars_os()
+        .map(|(k, v)| {
+            (
+                k.to_string_lossy().to_string(),
+                v.to_string_lossy().to_string(),
+            )
+        })
+        .collect();
+    vars.sort();
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for (name, value) in vars {
+        // names like `a-b` can not be read back
+        if !tools::re_contains(&name, r"^[a-zA-Z_][a-zA-Z0-9_]*$") {
+            continue;
+        }
+        if writeln!(handle, "export {}={}", name, tools::quote_word(&value)).is_err() {
+            return 1;
+        }
     }
+    0
+}
 
-    let _line;
-    if let Ok(re) = Regex::new(r"^ *export +") {
-        if !re.is_match(line) {
-            println_stderr!("export: invalid command?");
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    let mut args = &tokens[1..];
+    let mut unexport = false;
+    while let Some(arg) = args.first() {
+        match arg.1.as_str() {
+            "-n" => unexport = true,
+            "-p" => {}
+            "--" => {
+                args = &args[1..];
+                break;
+            }
+            _ => break,
+        }
+        args = &args[1..];
+    }
+    if args.is_empty() {
+        if unexport {
+            println_stderr!("usage: export -n NAME ...");
             return 1;
         }
-        _line = re.replace_all(line, "");
-    } else {
-        println_stderr!("cicada: re new error");
-        return 2;
+        return list_exported();
     }
 
-    let args = parsers::parser_line::cmd_to_tokens(&_line);
-    for (sep, token) in args {
-        if sep == "`" {
-            continue;
+    let re = match Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(=(.*))?$") {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: Regex error: {:?}", e);
+            return 1;
         }
-        if let Ok(re) = Regex::new(r" *([a-zA-Z0-9_]+)=(.*) *") {
-            if !re.is_match(&token) {
+    };
+
+    let mut status = 0;
+    for (_, text) in args {
+        let caps = match re.captures(text) {
+            Some(x) => x,
+            None => {
+                println_stderr!("cicada: export: `{}': not a valid identifier", text);
+                status = 1;
                 continue;
             }
-            for cap in re.captures_iter(&token) {
-                let mut _value = tools::unquote(&cap[2]);
-                if tools::needs_extend_home(&_value) {
-                    tools::extend_home(&mut _value);
-                }
-                let value = shell::extend_env_blindly(sh, &_value);
-                env::set_var(&cap[1], &value);
+        };
+        let name = &caps[1];
+        let value = caps.get(3).map(|x| {
+            let value = tools::unquote(x.as_str());
+            // like `export PATH=~/bin:$PATH`
+            if value == "~" || value.starts_with("~/") {
+                format!("{}{}", tools::get_user_home(), &value[1..])
+            } else {
+                value
             }
+        });
+
+        let result = if unexport {
+            let result = match value {
+                Some(ref x) => sh.set_env(name, x),
+                None => Ok(()),
+            };
+            sh.unexport_env(name);
+            result
         } else {
-            println_stderr!("cicada: re new error");
-            return 2;
+            // `export NAME` exports the shell variable, or an empty one
+            let value = value.unwrap_or_else(|| sh.get_env(name).unwrap_or_default());
+            sh.export_env(name, &value)
+        };
+        if let Err(e) = result {
+            println_stderr!("cicada: export: {}", e);
+            status = 1;
         }
     }
-    0
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::run;
+    use execute::run_procs;
+    use shell::Shell;
+
+    fn export(sh: &mut Shell, args: &[&str]) -> i32 {
+        let mut tokens = vec![("".to_string(), "export".to_string())];
+        for arg in args {
+            tokens.push(("".to_string(), arg.to_string()));
+        }
+        run(sh, &tokens)
+    }
+
+    #[test]
+    fn test_export() {
+        let mut sh = Shell::new();
+        let args = ["CICADA_EXPORT_A=\"a b\"", "CICADA_EXPORT_B=1"];
+        assert_eq!(export(&mut sh, &args), 0);
+        assert_eq!(env::var("CICADA_EXPORT_A"), Ok("a b".to_string()));
+        assert_eq!(env::var("CICADA_EXPORT_B"), Ok("1".to_string()));
+
+        sh.set_env("CICADA_EXPORT_C", "c").unwrap();
+        assert!(env::var("CICADA_EXPORT_C").is_err());
+        assert_eq!(export(&mut sh, &["CICADA_EXPORT_C"]), 0);
+        assert_eq!(env::var("CICADA_EXPORT_C"), Ok("c".to_string()));
+
+        assert_eq!(export(&mut sh, &["-n", "CICADA_EXPORT_C", "CICADA_EXPORT_B=2"]), 0);
+        assert!(env::var("CICADA_EXPORT_C").is_err());
+        assert!(env::var("CICADA_EXPORT_B").is_err());
+        assert_eq!(sh.get_env("CICADA_EXPORT_B"), Some("2".to_string()));
+
+        assert_eq!(export(&mut sh, &["1CICADA"]), 1);
+        assert_eq!(export(&mut sh, &["-n"]), 1);
+    }
+
+    #[test]
+    fn test_export_p_read_back() {
+        let path = env::temp_dir().join("cicada-test-export-p.sh");
+        let path = path.to_string_lossy();
+        let mut sh = Shell::new();
+        let args = ["CICADA_EXPORT_P=\"a 'b' c\"", "CICADA_EXPORT_Q='$x \"y\"'"];
+        assert_eq!(export(&mut sh, &args), 0);
+        let line = format!("export -p > {}", path);
+        assert_eq!(run_procs(&mut sh, &line, false), 0);
+        let text = fs::read_to_string(&*path).unwrap();
+        assert!(text.contains("export CICADA_EXPORT_P="));
+
+        for name in &["CICADA_EXPORT_P", "CICADA_EXPORT_Q"] {
+            assert_eq!(export(&mut sh, &["-n", name]), 0);
+            sh.remove_env(name).unwrap();
+            assert!(env::var(name).is_err());
+        }
+        let line = format!("source {}", path);
+        assert_eq!(run_procs(&mut sh, &line, false), 0);
+        assert_eq!(env::var("CICADA_EXPORT_P"), Ok("a 'b' c".to_string()));
+        assert_eq!(env::var("CICADA_EXPORT_Q"), Ok("$x \"y\"".to_string()));
+        let _ = fs::remove_file(&*path);
+    }
 }
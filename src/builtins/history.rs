@@ -12,7 +12,7 @@ pub fn run(tokens: &Vec<(String, String)>) -> i32 {
     let hfile = history::get_history_file();
     let path = Path::new(hfile.as_str());
     if !path.exists() {
-        println_stderr!("no history file.");
+        println_stderr!("cicada: history: no history file");
         return 1;
     }
 
@@ -22,10 +22,10 @@ pub fn run(tokens: &Vec<(String, String)>) -> i32 {
         } else if args.len() == 2 {
             search_history(&conn, args[1].as_str());
         } else {
-            println_stderr!("history: only take one arg");
+            println_stderr!("cicada: history: only take one arg");
         }
     } else {
-        println_stderr!("history: history file open error.");
+        println_stderr!("cicada: history: history file open error");
         return 1;
     }
     0
@@ -34,7 +34,7 @@ pub fn run(tokens: &Vec<(String, String)>) -> i32 {
 fn list_current_history(conn: &sqlite::Connection) -> i32 {
     let history_table = history::get_history_table();
     let q = format!(
-        "SELECT inp FROM {} ORDER BY tsb desc limit 20;",
+        "SELECT rowid, inp FROM {} ORDER BY tsb desc limit 20;",
         history_table
     );
     match conn.prepare(q) {
@@ -44,26 +44,27 @@ fn list_current_history(conn: &sqlite::Connection) -> i32 {
                 match statement.next() {
                     Ok(x) => {
                         if let State::Row = x {
-                            if let Ok(_x) = statement.read::<String>(0) {
-                                vec.push(_x);
+                            let rowid = statement.read::<i64>(0).unwrap_or(0);
+                            if let Ok(_x) = statement.read::<String>(1) {
+                                vec.push((rowid, _x));
                             }
                         } else {
                             break;
                         }
                     }
                     Err(e) => {
-                        println_stderr!("history: statement.next error: {:?}", e);
+                        println_stderr!("cicada: history: statement.next error: {:?}", e);
                         return 1;
                     }
                 }
             }
 
-            for (i, elem) in vec.iter().rev().enumerate() {
-                println!("{}: {}", i, elem);
+            for (rowid, elem) in vec.iter().rev() {
+                println!("{}: {}", rowid, elem);
             }
         }
         Err(e) => {
-            println_stderr!("history: prepare error - {:?}", e);
+            println_stderr!("cicada: history: prepare error - {:?}", e);
             return 1;
         }
     }
@@ -73,7 +74,7 @@ fn list_current_history(conn: &sqlite::Connection) -> i32 {
 fn search_history(conn: &sqlite::Connection, q: &str) {
     let history_table = history::get_history_table();
     let q = format!(
-        "SELECT inp FROM {}
+        "SELECT rowid, inp FROM {}
                      WHERE inp like '%{}%'
                      ORDER BY tsb desc limit 20;",
         history_table, q
@@ -85,25 +86,26 @@ fn search_history(conn: &sqlite::Connection, q: &str) {
                 match statement.next() {
                     Ok(x) => {
                         if let State::Row = x {
-                            if let Ok(_x) = statement.read::<String>(0) {
-                                vec.push(_x);
+                            let rowid = statement.read::<i64>(0).unwrap_or(0);
+                            if let Ok(_x) = statement.read::<String>(1) {
+                                vec.push((rowid, _x));
                             }
                         } else {
                             break;
                         }
                     }
                     Err(e) => {
-                        println_stderr!("history: statement.next error: {:?}", e);
+                        println_stderr!("cicada: history: statement.next error: {:?}", e);
                         return;
                     }
                 }
             }
-            for (i, elem) in vec.iter().rev().enumerate() {
-                println!("{}: {}", i, elem);
+            for (rowid, elem) in vec.iter().rev() {
+                println!("{}: {}", rowid, elem);
             }
         }
         Err(e) => {
-            println_stderr!("history: prepare error - {:?}", e);
+            println_stderr!("cicada: history: prepare error - {:?}", e);
         }
     }
 }
@@ -0,0 +1,42 @@
+use std::io::Write;
+
+use regex::Regex;
+
+use parsers;
+use shell;
+
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    if sh.local_frames.is_empty() {
+        println_stderr!("cicada: local: can only be used in a function");
+        return 1;
+    }
+
+    let re = match Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(=(.*))?$") {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: Regex error: {:?}", e);
+            return 1;
+        }
+    };
+
+    let mut status = 0;
+    for (_, text) in &tokens[1..] {
+        let caps = match re.captures(text) {
+            Some(x) => x,
+            None => {
+                println_stderr!("cicada: local: `{}': not a valid identifier", text);
+                status = 1;
+                continue;
+            }
+        };
+        let value = match caps.get(3) {
+            Some(x) => parsers::parser_line::unquote(x.as_str()),
+            None => String::new(),
+        };
+        if let Err(e) = sh.set_local(&caps[1], &value) {
+            println_stderr!("cicada: local: {}", e);
+            status = 1;
+        }
+    }
+    status
+}
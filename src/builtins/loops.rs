@@ -0,0 +1,50 @@
+use std::io::Write;
+
+use shell;
+use types::Tokens;
+
+/// Parse the `n` of `break [n]` and `continue [n]`, limited to the
+/// number of enclosing loops.
+fn get_loop_count(sh: &shell::Shell, name: &str, tokens: &Tokens) -> Result<usize, i32> {
+    if sh.loop_depth == 0 {
+        println_stderr!(
+            "cicada: {}: only meaningful in a `for', `while', or `until' loop",
+            name
+        );
+        return Err(0);
+    }
+    if tokens.len() > 2 {
+        println_stderr!("cicada: {}: too many arguments", name);
+        return Err(1);
+    }
+    if tokens.len() == 1 {
+        return Ok(1);
+    }
+    match tokens[1].1.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n.min(sh.loop_depth)),
+        _ => {
+            println_stderr!("cicada: {}: {}: loop count out of range", name, tokens[1].1);
+            Err(1)
+        }
+    }
+}
+
+pub fn run_break(sh: &mut shell::Shell, tokens: &Tokens) -> i32 {
+    match get_loop_count(sh, "break", tokens) {
+        Ok(n) => {
+            sh.break_count = n;
+            0
+        }
+        Err(status) => status,
+    }
+}
+
+pub fn run_continue(sh: &mut shell::Shell, tokens: &Tokens) -> i32 {
+    match get_loop_count(sh, "continue", tokens) {
+        Ok(n) => {
+            sh.continue_count = n;
+            0
+        }
+        Err(status) => status,
+    }
+}
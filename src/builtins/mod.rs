@@ -1,7 +1,58 @@
+pub mod alias;
 pub mod cd;
 pub mod cinfo;
 pub mod exec;
 pub mod exit;
 pub mod export;
 pub mod history;
+pub mod local;
+pub mod loops;
+pub mod readonly;
+pub mod returns;
+pub mod set;
+pub mod source;
+pub mod test;
+pub mod unalias;
+pub mod unset;
 pub mod vox;
+
+use shell;
+use types::Tokens;
+
+/// Runs a built-in command with its tokens, the command name included;
+/// the bool is whether the shell is on a tty.
+pub type Builtin = fn(&mut shell::Shell, &Tokens, bool) -> i32;
+
+/// All built-in commands. The executor runs them from here, and
+/// completion and highlighting take their names from here.
+pub const BUILTINS: &[(&str, Builtin)] = &[
+    ("alias", |sh, tokens, _| alias::run(sh, tokens)),
+    ("break", |sh, tokens, _| loops::run_break(sh, tokens)),
+    ("cd", |sh, tokens, _| cd::run(sh, tokens)),
+    ("cinfo", |_, tokens, _| cinfo::run(tokens)),
+    ("continue", |sh, tokens, _| loops::run_continue(sh, tokens)),
+    ("exec", |_, tokens, _| exec::run(tokens)),
+    ("exit", |sh, tokens, _| exit::run(sh, tokens)),
+    ("export", |sh, tokens, _| export::run(sh, tokens)),
+    ("history", |_, tokens, _| history::run(tokens)),
+    ("local", |sh, tokens, _| local::run(sh, tokens)),
+    ("readonly", |sh, tokens, _| readonly::run(sh, tokens)),
+    ("return", |sh, tokens, _| returns::run(sh, tokens)),
+    ("set", |sh, tokens, _| set::run(sh, tokens)),
+    ("source", |sh, tokens, tty| source::run(sh, tokens, tty)),
+    (".", |sh, tokens, tty| source::run(sh, tokens, tty)),
+    ("test", |_, tokens, _| test::run(tokens)),
+    ("[", |_, tokens, _| test::run(tokens)),
+    ("unalias", |sh, tokens, _| unalias::run(sh, tokens)),
+    ("unset", |sh, tokens, _| unset::run(sh, tokens)),
+    ("vox", |sh, tokens, _| vox::run(sh, tokens)),
+];
+
+pub fn find(name: &str) -> Option<Builtin> {
+    BUILTINS.iter().find(|x| x.0 == name).map(|x| x.1)
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    find(name).is_some()
+}
+
@@ -0,0 +1,61 @@
+use std::io::{self, Write};
+
+use regex::Regex;
+
+use parsers;
+use shell;
+use tools;
+
+fn list_readonly(sh: &shell::Shell) -> i32 {
+    let mut names: Vec<&String> = sh.readonly.iter().collect();
+    names.sort();
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for name in names {
+        let result = match sh.get_env(name) {
+            Some(value) => writeln!(handle, "readonly {}={}", name, tools::quote_word(&value)),
+            None => writeln!(handle, "readonly {}", name),
+        };
+        if result.is_err() {
+            return 1;
+        }
+    }
+    0
+}
+
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    if tokens.len() == 1 || (tokens.len() == 2 && tokens[1].1 == "-p") {
+        return list_readonly(sh);
+    }
+
+    let re = match Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(=(.*))?$") {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: Regex error: {:?}", e);
+            return 1;
+        }
+    };
+
+    let mut status = 0;
+    for (_, text) in &tokens[1..] {
+        let caps = match re.captures(text) {
+            Some(x) => x,
+            None => {
+                println_stderr!("cicada: readonly: `{}': not a valid identifier", text);
+                status = 1;
+                continue;
+            }
+        };
+        let name = &caps[1];
+        if let Some(value) = caps.get(3) {
+            let value = parsers::parser_line::unquote(value.as_str());
+            if let Err(e) = sh.set_env(name, &value) {
+                println_stderr!("cicada: readonly: {}", e);
+                status = 1;
+                continue;
+            }
+        }
+        sh.set_readonly(name);
+    }
+    status
+}
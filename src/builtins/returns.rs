@@ -0,0 +1,29 @@
+use std::io::Write;
+
+use shell;
+
+/// `return [n]` leaves the current function or sourced file with status
+/// `n`, or with the status of the last command.
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    if sh.local_frames.is_empty() && sh.source_depth == 0 {
+        println_stderr!("cicada: return: can only `return' from a function or sourced script");
+        return 1;
+    }
+    if tokens.len() > 2 {
+        println_stderr!("cicada: return: too many arguments");
+        return 1;
+    }
+    let status = if tokens.len() == 2 {
+        match tokens[1].1.parse::<i32>() {
+            Ok(x) => x,
+            Err(_) => {
+                println_stderr!("cicada: return: {}: numeric argument required", tokens[1].1);
+                2
+            }
+        }
+    } else {
+        sh.previous_status
+    };
+    sh.returning = true;
+    status
+}
@@ -0,0 +1,49 @@
+use std::io::{self, Write};
+
+use shell;
+use tools;
+
+/// List shell variables; it writes to stdout directly, so that the
+/// listing can be redirected, e.g. `set > vars.txt`.
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    if tokens.len() > 1 {
+        println_stderr!("cicada: set: options are not supported");
+        println_stderr!("usage: set");
+        return 1;
+    }
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for (name, value) in sh.get_vars() {
+        if writeln!(handle, "{}={}", name, tools::quote_word(&value)).is_err() {
+            return 1;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use execute::run_procs;
+    use shell::Shell;
+
+    #[test]
+    fn test_set_redirected() {
+        let path = env::temp_dir().join("cicada-test-set.txt");
+        let path = path.to_string_lossy();
+        let mut sh = Shell::new();
+        sh.set_env("CICADA_SET_X", "a b").unwrap();
+        let line = format!("set > {}", path);
+        assert_eq!(run_procs(&mut sh, &line, false), 0);
+        let text = fs::read_to_string(&*path).unwrap();
+        assert!(text.lines().any(|x| x == "CICADA_SET_X='a b'"));
+
+        let line = format!("set | grep CICADA_SET_ > {}", path);
+        assert_eq!(run_procs(&mut sh, &line, false), 0);
+        assert_eq!(fs::read_to_string(&*path).unwrap(), "CICADA_SET_X='a b'\n");
+        let _ = fs::remove_file(&*path);
+    }
+}
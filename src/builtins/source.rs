@@ -0,0 +1,186 @@
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem;
+use std::path::Path;
+
+use scripting;
+use shell;
+use tools;
+
+/// Limit of nested `source` commands, e.g. for files sourcing themselves.
+const MAX_SOURCE_DEPTH: usize = 100;
+
+/// Find the file to source: names without a `/` are searched in `$PATH`
+/// first, then in the current directory.
+fn find_file(name: &str) -> Option<String> {
+    if name.contains('/') {
+        return if Path::new(name).is_file() {
+            Some(name.to_string())
+        } else {
+            None
+        };
+    }
+    if let Ok(paths) = env::var("PATH") {
+        for dir in paths.split(':') {
+            let path = Path::new(dir).join(name);
+            if path.is_file() {
+                return Some(path.to_string_lossy().to_string());
+            }
+        }
+    }
+    if Path::new(name).is_file() {
+        return Some(name.to_string());
+    }
+    None
+}
+
+/// Run a file in the current shell, so its variables, aliases, functions
+/// and directory changes stay. Extra arguments become the positional
+/// parameters while the file runs.
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)], tty: bool) -> i32 {
+    let cmd = &tokens[0].1;
+    if tokens.len() < 2 {
+        println_stderr!("cicada: {}: filename argument required", cmd);
+        println_stderr!("usage: {} FILE [ARGS...]", cmd);
+        return 2;
+    }
+    let name = &tokens[1].1;
+    let path = match find_file(name) {
+        Some(x) => x,
+        None => {
+            println_stderr!("cicada: {}: {}: No such file or directory", cmd, name);
+            return 1;
+        }
+    };
+    let args = if tokens.len() > 2 {
+        Some(tokens[2..].iter().map(|x| x.1.clone()).collect())
+    } else {
+        None
+    };
+    source_file(sh, &path, args, tty)
+}
+
+/// Run commands in the file at `path`, reporting errors with the file
+/// name and line numbers.
+pub fn source_file(sh: &mut shell::Shell, path: &str, args: Option<Vec<String>>, tty: bool) -> i32 {
+    let mut text = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+        println_stderr!("cicada: {}: {}", path, e);
+        return 1;
+    }
+    if sh.source_depth >= MAX_SOURCE_DEPTH {
+        println_stderr!("cicada: {}: maximum source depth exceeded", path);
+        return 1;
+    }
+
+    let saved_args = args.map(|x| mem::replace(&mut sh.args, x));
+    let saved_name = mem::replace(&mut sh.script_name, path.to_string());
+    let saved_lineno = mem::replace(&mut sh.lineno, 1);
+    let saved_location = tools::set_error_location("");
+    sh.source_depth += 1;
+
+    let status = scripting::run_script(sh, &text, tty);
+
+    sh.source_depth -= 1;
+    tools::set_error_location(&saved_location);
+    sh.returning = false;
+    sh.lineno = saved_lineno;
+    sh.script_name = saved_name;
+    if let Some(args) = saved_args {
+        sh.args = args;
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use super::run;
+    use execute::run_procs;
+    use shell::Shell;
+
+    #[test]
+    fn test_source() {
+        let path = env::temp_dir().join("cicada-test-source.sh");
+        let path = path.to_string_lossy().to_string();
+        let mut file = File::create(&path).expect("error when create file");
+        file.write_all(b"SOURCED_A=$1\nf() { echo; }\nreturn 3\nSOURCED_B=no\n")
+            .expect("error when write file");
+
+        let mut sh = Shell::new();
+        sh.lineno = 9;
+        let tokens = vec![
+            ("".to_string(), "source".to_string()),
+            ("".to_string(), path.clone()),
+            ("".to_string(), "foo".to_string()),
+        ];
+        let status = run(&mut sh, &tokens, false);
+        fs::remove_file(&path).expect("error when rm file");
+
+        assert_eq!(status, 3);
+        assert_eq!(sh.get_env("SOURCED_A"), Some("foo".to_string()));
+        assert_eq!(sh.get_env("SOURCED_B"), None);
+        assert!(sh.funcs.contains_key("f"));
+        assert!(sh.args.is_empty());
+        assert!(!sh.returning);
+        assert_eq!(sh.lineno, 9);
+
+        let tokens = vec![
+            ("".to_string(), ".".to_string()),
+            ("".to_string(), "/no/such/file".to_string()),
+        ];
+        assert_eq!(run(&mut sh, &tokens, false), 1);
+    }
+
+    #[test]
+    fn test_source_syntax_error() {
+        let path = env::temp_dir().join("cicada-test-source-error.sh");
+        let path = path.to_string_lossy().to_string();
+        let mut file = File::create(&path).expect("error when create file");
+        file.write_all(b"SOURCED_C=1\nif true; then\n  fi fi\nSOURCED_D=2\nSOURCED_E=3; done\n")
+            .expect("error when write file");
+
+        let mut sh = Shell::new();
+        let tokens = vec![
+            ("".to_string(), "source".to_string()),
+            ("".to_string(), path.clone()),
+        ];
+        let status = run(&mut sh, &tokens, false);
+        fs::remove_file(&path).expect("error when rm file");
+
+        assert_eq!(status, 2);
+        assert_eq!(sh.get_env("SOURCED_C"), Some("1".to_string()));
+        assert_eq!(sh.get_env("SOURCED_D"), Some("2".to_string()));
+        assert_eq!(sh.get_env("SOURCED_E"), Some("3".to_string()));
+    }
+
+    #[test]
+    fn test_source_builtin_error() {
+        // RC files are run by `source_file()` too
+        let dir = env::temp_dir();
+        let path = dir.join("cicada-test-rc-error.sh");
+        let path = path.to_string_lossy().to_string();
+        let err = dir.join("cicada-test-rc-error.txt");
+        let err = err.to_string_lossy().to_string();
+        let mut file = File::create(&path).expect("error when create file");
+        file.write_all(b"X=1\n\ncd /no/such/dir\n")
+            .expect("error when write file");
+
+        let mut sh = Shell::new();
+        let line = format!("source {} 2> {}", path, err);
+        run_procs(&mut sh, &line, false);
+        let text = fs::read_to_string(&err).expect("error when read file");
+        fs::remove_file(&path).expect("error when rm file");
+        fs::remove_file(&err).expect("error when rm file");
+
+        let expected = format!(
+            "cicada: {}:3: cd: /no/such/dir: No such file or directory (os error 2)\n\n",
+            path
+        );
+        assert_eq!(text, expected);
+    }
+}
@@ -0,0 +1,479 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+use glob;
+use libc;
+use regex::{self, Regex};
+
+use shell;
+use tools;
+
+const UNARY_OPS: &[&str] = &[
+    "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-O", "-G", "-p", "-r", "-s",
+    "-S", "-t", "-u", "-w", "-x", "-z",
+];
+
+const BINARY_OPS: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+/// A word of a condition, and whether it was quoted.
+type Word = (String, bool);
+
+/// Evaluator of conditions of both `test` (`[ ]`) and `[[ ]]`.
+struct Cond {
+    words: Vec<Word>,
+    pos: usize,
+    /// `[[ ]]` mode: `&&`, `||`, pattern matching with `==`, and `=~`
+    double: bool,
+    /// groups captured by the last `=~`
+    matches: Option<Vec<String>>,
+}
+
+impl Cond {
+    fn peek(&self, n: usize) -> Option<&Word> {
+        self.words.get(self.pos + n)
+    }
+
+    /// Whether the next word is the unquoted operator `op`.
+    fn peek_op(&self, op: &str) -> bool {
+        match self.peek(0) {
+            Some(&(ref w, quoted)) => !quoted && w == op,
+            None => false,
+        }
+    }
+
+    fn next(&mut self) -> Result<Word, String> {
+        match self.words.get(self.pos) {
+            Some(x) => {
+                self.pos += 1;
+                Ok(x.clone())
+            }
+            None => Err(String::from("argument expected")),
+        }
+    }
+
+    fn parse(&mut self) -> Result<bool, String> {
+        if self.words.is_empty() {
+            return Ok(false);
+        }
+        let result = self.parse_or()?;
+        if let Some((w, _)) = self.peek(0) {
+            return Err(format!("{}: unexpected argument", w));
+        }
+        Ok(result)
+    }
+
+    fn parse_or(&mut self) -> Result<bool, String> {
+        let op = if self.double { "||" } else { "-o" };
+        let mut result = self.parse_and()?;
+        while self.peek_op(op) {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, String> {
+        let op = if self.double { "&&" } else { "-a" };
+        let mut result = self.parse_not()?;
+        while self.peek_op(op) {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, String> {
+        if self.peek_op("!") && self.peek(1).is_some() {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool, String> {
+        let (word, quoted) = self.next()?;
+        if !quoted && word == "(" && self.peek(0).is_some() {
+            let result = self.parse_or()?;
+            if !self.peek_op(")") {
+                return Err(String::from("`)' expected"));
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+
+        let binary = match self.peek(0) {
+            Some(&(ref op, false)) => {
+                (BINARY_OPS.contains(&op.as_str()) || (self.double && op == "=~"))
+                    && self.peek(1).is_some()
+            }
+            _ => false,
+        };
+        if binary {
+            let (op, _) = self.next()?;
+            let right = self.next()?;
+            return self.eval_binary(&word, &op, &right);
+        }
+
+        if !quoted && UNARY_OPS.contains(&word.as_str()) && self.peek(0).is_some() {
+            let (arg, _) = self.next()?;
+            return eval_unary(&word, &arg);
+        }
+        Ok(!word.is_empty())
+    }
+
+    fn eval_binary(&mut self, left: &str, op: &str, right: &Word) -> Result<bool, String> {
+        let &(ref right, quoted) = right;
+        match op {
+            "=" | "==" | "!=" => {
+                let matched = if self.double {
+                    match_pattern(left, right, quoted)
+                } else {
+                    left == right
+                };
+                Ok(matched == (op != "!="))
+            }
+            "=~" => {
+                let ptn = if quoted {
+                    regex::escape(right)
+                } else {
+                    right.to_string()
+                };
+                let re = Regex::new(&ptn).map_err(|_| format!("{}: invalid regex", right))?;
+                match re.captures(left) {
+                    Some(caps) => {
+                        let groups = caps
+                            .iter()
+                            .map(|m| m.map_or(String::new(), |x| x.as_str().to_string()))
+                            .collect();
+                        self.matches = Some(groups);
+                        Ok(true)
+                    }
+                    None => {
+                        self.matches = Some(Vec::new());
+                        Ok(false)
+                    }
+                }
+            }
+            "<" => Ok(left < right.as_str()),
+            ">" => Ok(left > right.as_str()),
+            "-nt" | "-ot" => {
+                let (a, b) = (mtime(left), mtime(right));
+                Ok(match (a, b) {
+                    (Some(a), Some(b)) => (op == "-nt" && a > b) || (op == "-ot" && a < b),
+                    (Some(_), None) => op == "-nt",
+                    (None, Some(_)) => op == "-ot",
+                    (None, None) => false,
+                })
+            }
+            "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+                (Ok(a), Ok(b)) => Ok(a.dev() == b.dev() && a.ino() == b.ino()),
+                _ => Ok(false),
+            },
+            _ => {
+                let a = parse_int(left)?;
+                let b = parse_int(right)?;
+                Ok(match op {
+                    "-eq" => a == b,
+                    "-ne" => a != b,
+                    "-lt" => a < b,
+                    "-le" => a <= b,
+                    "-gt" => a > b,
+                    _ => a >= b,
+                })
+            }
+        }
+    }
+}
+
+fn parse_int(s: &str) -> Result<i64, String> {
+    s.trim()
+        .parse::<i64>()
+        .map_err(|_| format!("{}: integer expression expected", s))
+}
+
+fn mtime(path: &str) -> Option<(i64, i64)> {
+    fs::metadata(path).ok().map(|m| (m.mtime(), m.mtime_nsec()))
+}
+
+fn access(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        Ok(p) => unsafe { libc::access(p.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// Match `[[ ]]` patterns, which use the glob syntax unless quoted.
+fn match_pattern(word: &str, pattern: &str, quoted: bool) -> bool {
+    if quoted {
+        return word == pattern;
+    }
+    match glob::Pattern::new(pattern) {
+        Ok(p) => p.matches(word),
+        Err(_) => word == pattern,
+    }
+}
+
+fn eval_unary(op: &str, arg: &str) -> Result<bool, String> {
+    match op {
+        "-n" => return Ok(!arg.is_empty()),
+        "-z" => return Ok(arg.is_empty()),
+        "-t" => {
+            let fd = parse_int(arg)?;
+            return Ok(unsafe { libc::isatty(fd as libc::c_int) == 1 });
+        }
+        "-r" => return Ok(access(arg, libc::R_OK)),
+        "-w" => return Ok(access(arg, libc::W_OK)),
+        "-x" => return Ok(access(arg, libc::X_OK)),
+        _ => {}
+    }
+    if op == "-h" || op == "-L" {
+        return Ok(fs::symlink_metadata(arg)
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false));
+    }
+    let meta = match fs::metadata(arg) {
+        Ok(x) => x,
+        Err(_) => return Ok(false),
+    };
+    let file_type = meta.file_type();
+    let mode = meta.permissions().mode();
+    Ok(match op {
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-d" => file_type.is_dir(),
+        "-e" => true,
+        "-f" => file_type.is_file(),
+        "-g" => mode & 0o2000 != 0,
+        "-k" => mode & 0o1000 != 0,
+        "-u" => mode & 0o4000 != 0,
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-s" => meta.len() > 0,
+        "-O" => meta.uid() == unsafe { libc::geteuid() },
+        _ => meta.gid() == unsafe { libc::getegid() },
+    })
+}
+
+fn to_status(name: &str, result: Result<bool, String>) -> i32 {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            println_stderr!("cicada: {}: {}", name, e);
+            2
+        }
+    }
+}
+
+/// The `test` and `[` builtins.
+pub fn run(tokens: &[(String, String)]) -> i32 {
+    let name = tokens[0].1.clone();
+    let mut args = &tokens[1..];
+    if name == "[" {
+        match args.last() {
+            Some(x) if x.1 == "]" => args = &args[..args.len() - 1],
+            _ => {
+                println_stderr!("cicada: [: missing `]'");
+                return 2;
+            }
+        }
+    }
+    let mut cond = Cond {
+        words: args.iter().map(|x| (x.1.clone(), false)).collect(),
+        pos: 0,
+        double: false,
+        matches: None,
+    };
+    to_status(&name, cond.parse())
+}
+
+/// Expand a part of a `[[ ]]` word: variables and command substitutions,
+/// but neither globbing nor word splitting.
+fn expand_part(sh: &shell::Shell, part: &str) -> String {
+    if part.contains("$(") || part.contains('`') {
+        let mut line = part.to_string();
+        tools::expand_word(sh, &mut line);
+        return line;
+    }
+    if part.contains('$') {
+        return shell::extend_env_blindly(sh, part);
+    }
+    part.to_string()
+}
+
+/// Split the inside of `[[ ]]` into words, removing quotes and expanding
+/// the words.
+fn split_cond_words(sh: &shell::Shell, text: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut part = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut quote: Option<char> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some('\'') => {
+                if c == '\'' {
+                    quote = None;
+                } else {
+                    word.push(c);
+                }
+                continue;
+            }
+            Some(q) => {
+                if c == q {
+                    word.push_str(&expand_part(sh, &part));
+                    part.clear();
+                    quote = None;
+                } else {
+                    part.push(c);
+                }
+                continue;
+            }
+            None => {}
+        }
+        match c {
+            '\'' | '"' => {
+                word.push_str(&expand_part(sh, &part));
+                part.clear();
+                quote = Some(c);
+                quoted = true;
+                in_word = true;
+            }
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    part.push(next);
+                }
+                in_word = true;
+            }
+            ' ' | '\t' | '\n' => {
+                if in_word {
+                    word.push_str(&expand_part(sh, &part));
+                    words.push((word.clone(), quoted));
+                    word.clear();
+                    part.clear();
+                    in_word = false;
+                    quoted = false;
+                }
+            }
+            _ => {
+                part.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(String::from("unexpected EOF while looking for matching quote"));
+    }
+    if in_word {
+        word.push_str(&expand_part(sh, &part));
+        words.push((word, quoted));
+    }
+    Ok(words)
+}
+
+/// Set `BASH_REMATCH` to the whole match of the last `=~`, and
+/// `BASH_REMATCH_1`, `BASH_REMATCH_2`, ... to its groups.
+fn set_rematch(sh: &mut shell::Shell, groups: &[String]) {
+    let mut n = 1;
+    while sh.get_env(&format!("BASH_REMATCH_{}", n)).is_some() {
+        let _ = sh.remove_env(&format!("BASH_REMATCH_{}", n));
+        n += 1;
+    }
+    let whole = groups.first().cloned().unwrap_or_default();
+    let _ = sh.set_env("BASH_REMATCH", &whole);
+    for (i, group) in groups.iter().enumerate().skip(1) {
+        let _ = sh.set_env(&format!("BASH_REMATCH_{}", i), group);
+    }
+}
+
+/// Run a `[[ ... ]]` command line.
+pub fn run_cond(sh: &mut shell::Shell, line: &str) -> i32 {
+    let line = line.trim();
+    if !line.ends_with("]]") || line.len() < 4 {
+        println_stderr!("cicada: [[: expected `]]'");
+        return 2;
+    }
+    let words = match split_cond_words(sh, &line[2..line.len() - 2]) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: [[: {}", e);
+            return 2;
+        }
+    };
+    let mut cond = Cond {
+        words,
+        pos: 0,
+        double: true,
+        matches: None,
+    };
+    let result = cond.parse();
+    if let Some(groups) = cond.matches {
+        set_rematch(sh, &groups);
+    }
+    to_status("[[", result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use super::run_cond;
+    use shell::Shell;
+
+    fn test(args: &[&str]) -> i32 {
+        let mut tokens = vec![("".to_string(), "test".to_string())];
+        for arg in args {
+            tokens.push(("".to_string(), arg.to_string()));
+        }
+        run(&tokens)
+    }
+
+    #[test]
+    fn test_test() {
+        assert_eq!(test(&[]), 1);
+        assert_eq!(test(&["a"]), 0);
+        assert_eq!(test(&[""]), 1);
+        assert_eq!(test(&["-n"]), 0);
+        assert_eq!(test(&["-z", ""]), 0);
+        assert_eq!(test(&["a", "=", "a"]), 0);
+        assert_eq!(test(&["a", "!=", "a"]), 1);
+        assert_eq!(test(&["a*", "=", "ab"]), 1);
+        assert_eq!(test(&["10", "-gt", "9"]), 0);
+        assert_eq!(test(&["10", "-le", "9"]), 1);
+        assert_eq!(test(&["x", "-eq", "1"]), 2);
+        assert_eq!(test(&["!", "-d", "/"]), 1);
+        assert_eq!(test(&["-d", "/", "-a", "-f", "/"]), 1);
+        assert_eq!(test(&["-d", "/", "-o", "-f", "/"]), 0);
+        assert_eq!(test(&["(", "a", "=", "b", ")", "-o", "c"]), 0);
+        assert_eq!(test(&["-e", "/no/such/file"]), 1);
+        assert_eq!(test(&["a", "b"]), 2);
+
+        let tokens = vec![("".to_string(), "[".to_string()), ("".to_string(), "a".to_string())];
+        assert_eq!(run(&tokens), 2);
+    }
+
+    #[test]
+    fn test_run_cond() {
+        let mut sh = Shell::new();
+        sh.set_env("CICADA_COND_X", "foo.txt").unwrap();
+        assert_eq!(run_cond(&mut sh, "[[ $CICADA_COND_X == *.txt ]]"), 0);
+        assert_eq!(run_cond(&mut sh, "[[ $CICADA_COND_X == '*.txt' ]]"), 1);
+        assert_eq!(run_cond(&mut sh, "[[ -n $CICADA_COND_X && ! -d / ]]"), 1);
+        assert_eq!(run_cond(&mut sh, "[[ ( -z a || b < c ) && -d / ]]"), 0);
+        assert_eq!(run_cond(&mut sh, "[[ $CICADA_COND_X =~ ^([a-z]+)\\.(txt|md)$ ]]"), 0);
+        assert_eq!(sh.get_env("BASH_REMATCH"), Some("foo.txt".to_string()));
+        assert_eq!(sh.get_env("BASH_REMATCH_1"), Some("foo".to_string()));
+        assert_eq!(sh.get_env("BASH_REMATCH_2"), Some("txt".to_string()));
+        assert_eq!(run_cond(&mut sh, "[[ abc =~ x ]]"), 1);
+        assert_eq!(sh.get_env("BASH_REMATCH_1"), None);
+        assert_eq!(run_cond(&mut sh, "[[ a =~ ( ]]"), 2);
+        assert_eq!(run_cond(&mut sh, "[[ a"), 2);
+    }
+}
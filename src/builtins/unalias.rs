@@ -0,0 +1,35 @@
+use std::io::Write;
+
+use shell;
+
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    if tokens.len() == 2 && tokens[1].1 == "-a" {
+        sh.alias.clear();
+        sh.alias_global.clear();
+        sh.alias_suffix.clear();
+        return 0;
+    }
+    let mut names = &tokens[1..];
+    let for_suffix = !names.is_empty() && names[0].1 == "-s";
+    if for_suffix {
+        names = &names[1..];
+    }
+    if names.is_empty() {
+        println_stderr!("usage: unalias [-a] [-s] NAME ...");
+        return 1;
+    }
+
+    let mut status = 0;
+    for (_, name) in names {
+        let removed = if for_suffix {
+            sh.remove_alias_suffix(name)
+        } else {
+            sh.remove_alias(name)
+        };
+        if !removed {
+            println_stderr!("cicada: unalias: {}: not found", name);
+            status = 1;
+        }
+    }
+    status
+}
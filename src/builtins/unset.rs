@@ -0,0 +1,27 @@
+use std::io::Write;
+
+use shell;
+
+pub fn run(sh: &mut shell::Shell, tokens: &[(String, String)]) -> i32 {
+    let mut names = &tokens[1..];
+    let mut is_func = false;
+    if !names.is_empty() && (names[0].1 == "-v" || names[0].1 == "-f") {
+        is_func = names[0].1 == "-f";
+        names = &names[1..];
+    }
+    if names.is_empty() {
+        println_stderr!("usage: unset [-v | -f] NAME ...");
+        return 1;
+    }
+
+    let mut status = 0;
+    for (_, name) in names {
+        if is_func {
+            sh.remove_func(name);
+        } else if let Err(e) = sh.remove_env(name) {
+            println_stderr!("cicada: unset: {}", e);
+            status = 1;
+        }
+    }
+    status
+}
@@ -1,5 +1,6 @@
 use std::env;
 use std::fs::{self, read_dir};
+use std::io::Write;
 use std::path::Path;
 
 use parsers;
@@ -30,13 +31,13 @@ fn get_envs_home() -> String {
 fn list_envs() -> i32 {
     let home_envs = get_envs_home();
     if home_envs == "" {
-        println!("you need to set VIRTUALENV_HOME to use vox");
+        println_stderr!("cicada: vox: you need to set VIRTUALENV_HOME to use vox");
         return 1;
     }
     if !Path::new(home_envs.as_str()).exists() {
         match fs::create_dir_all(home_envs.as_str()) {
             Ok(_) => {}
-            Err(e) => println!("fs create_dir_all failed: {:?}", e),
+            Err(e) => println_stderr!("cicada: vox: {}: {}", home_envs, e),
         }
     }
 
@@ -61,13 +62,13 @@ fn list_envs() -> i32 {
 
 fn enter_env(sh: &shell::Shell, path: &str) -> i32 {
     if in_env() {
-        println!("vox: already in env");
+        println_stderr!("cicada: vox: already in env");
         return 1;
     }
     let home_envs = get_envs_home();
     let full_path = format!("{}/{}/bin/activate", home_envs, path);
     if !Path::new(full_path.as_str()).exists() {
-        println!("no such env: {}", full_path);
+        println_stderr!("cicada: vox: no such env: {}", full_path);
         return 1;
     }
     let path_env = format!("{}/{}", home_envs, path);
@@ -80,14 +81,14 @@ fn enter_env(sh: &shell::Shell, path: &str) -> i32 {
 
 fn exit_env(sh: &shell::Shell) -> i32 {
     if !in_env() {
-        println!("vox: not in an env");
+        println_stderr!("cicada: vox: not in an env");
         return 0;
     }
     let env_path;
     match env::var("PATH") {
         Ok(x) => env_path = x,
         Err(_) => {
-            println!("vox: cannot read PATH env");
+            println_stderr!("cicada: vox: cannot read PATH env");
             return 1;
         }
     }
@@ -113,8 +114,8 @@ pub fn run(sh: &shell::Shell, tokens: &Vec<(String, String)>) -> i32 {
     } else if args.len() == 2 && args[1] == "exit" {
         exit_env(sh)
     } else {
-        println!("vox: invalid command");
-        println!("usage: vox (ls | enter <env-name> | exit)");
+        println_stderr!("cicada: vox: invalid command");
+        println_stderr!("usage: vox (ls | enter <env-name> | exit)");
         1
     }
 }
@@ -0,0 +1,325 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use glob;
+use linefeed::complete::{Completer, Completion, Suffix};
+use linefeed::prompter::Prompter;
+use linefeed::terminal::Terminal;
+
+use completers::path;
+use parsers;
+
+const SUBCOMMANDS: &[&str] = &[
+    "add",
+    "bench",
+    "build",
+    "check",
+    "clean",
+    "doc",
+    "fetch",
+    "fix",
+    "generate-lockfile",
+    "help",
+    "init",
+    "install",
+    "locate-project",
+    "login",
+    "logout",
+    "metadata",
+    "new",
+    "owner",
+    "package",
+    "pkgid",
+    "publish",
+    "remove",
+    "run",
+    "rustc",
+    "rustdoc",
+    "search",
+    "test",
+    "tree",
+    "uninstall",
+    "update",
+    "vendor",
+    "version",
+    "yank",
+];
+
+pub struct CargoCompleter;
+
+impl<Term: Terminal> Completer<Term> for CargoCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        reader: &Prompter<Term>,
+        _start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        Some(complete_cargo(reader.buffer(), word))
+    }
+}
+
+/// The `(section, key, value)` entries of a `Cargo.toml`, e.g.
+/// `("bin", "name", "\"foo\"")` for `[[bin]]` tables. Arrays over
+/// several lines are joined into one value.
+fn parse_manifest(content: &str) -> Vec<(String, String, String)> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line
+                .trim_matches(|c| c == '[' || c == ']')
+                .trim()
+                .to_string();
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => continue,
+        };
+        let mut value = value.to_string();
+        if value.starts_with('[') && !value.contains(']') {
+            for more in lines.by_ref() {
+                value.push_str(more.trim());
+                if more.contains(']') {
+                    break;
+                }
+            }
+        }
+        entries.push((section.clone(), key.trim_matches('"').to_string(), value));
+    }
+    entries
+}
+
+/// The quoted strings in a value.
+fn strings(value: &str) -> Vec<String> {
+    value
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .map(|x| x.to_string())
+        .collect()
+}
+
+fn read_manifest(dir: &Path) -> Option<Vec<(String, String, String)>> {
+    let content = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    Some(parse_manifest(&content))
+}
+
+fn get_values(entries: &[(String, String, String)], section: &str, key: &str) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|x| x.0 == section && x.1 == key)
+        .flat_map(|x| strings(&x.2))
+        .collect()
+}
+
+/// Names of `.rs` files, and of dirs with a `main.rs`, in `dir`.
+fn target_files(dir: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(_) => return names,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if path.is_dir() && path.join("main.rs").exists() {
+            names.push(name);
+        } else if let Some(x) = name.strip_suffix(".rs") {
+            names.push(x.to_string());
+        }
+    }
+    names
+}
+
+/// Targets of a kind, i.e. `bin`, `example`, `test` or `bench`, from
+/// the manifest and the dirs cargo finds them in.
+fn get_targets(dir: &Path, entries: &[(String, String, String)], kind: &str) -> BTreeSet<String> {
+    let mut names: BTreeSet<String> = get_values(entries, kind, "name").into_iter().collect();
+    let target_dir = match kind {
+        "bin" => "src/bin",
+        "example" => "examples",
+        "test" => "tests",
+        "bench" => "benches",
+        _ => return names,
+    };
+    names.extend(target_files(&dir.join(target_dir)));
+    if kind == "bin" && dir.join("src/main.rs").exists() {
+        names.extend(get_values(entries, "package", "name"));
+    }
+    names
+}
+
+fn is_workspace(dir: &Path) -> bool {
+    match read_manifest(dir) {
+        Some(entries) => entries.iter().any(|e| e.0 == "workspace"),
+        None => false,
+    }
+}
+
+/// Names of the packages of the workspace, or of the package alone.
+fn get_packages(dir: &Path) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let root = dir.ancestors().find(|x| is_workspace(x)).unwrap_or(dir);
+    let entries = match read_manifest(root) {
+        Some(x) => x,
+        None => return names,
+    };
+    names.extend(get_values(&entries, "package", "name"));
+    for member in get_values(&entries, "workspace", "members") {
+        let pattern = root.join(&member).to_string_lossy().into_owned();
+        let dirs = match glob::glob(&pattern) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        for member_dir in dirs.flatten() {
+            if let Some(x) = read_manifest(&member_dir) {
+                names.extend(get_values(&x, "package", "name"));
+            }
+        }
+    }
+    names
+}
+
+/// The nearest dir with a `Cargo.toml`.
+fn find_package_dir() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .find(|x| x.join("Cargo.toml").is_file())
+        .map(|x| x.to_path_buf())
+}
+
+/// Subcommands, and the ones of `cargo-*` programs in `$PATH`.
+fn get_subcommands() -> BTreeSet<String> {
+    let mut names: BTreeSet<String> = SUBCOMMANDS.iter().map(|x| x.to_string()).collect();
+    let env_path = env::var("PATH").unwrap_or_default();
+    for dir in env_path.split(':') {
+        let entries = match fs::read_dir(dir) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(x) = name.strip_prefix("cargo-") {
+                if let Ok(m) = entry.metadata() {
+                    if path::is_executable(&m) {
+                        names.insert(x.to_string());
+                    }
+                }
+            }
+        }
+    }
+    names
+}
+
+fn complete_cargo(line: &str, word: &str) -> Vec<Completion> {
+    let args = parsers::parser_line::line_to_plain_tokens(line);
+    let done = if line.ends_with(' ') {
+        args.len()
+    } else {
+        args.len().saturating_sub(1)
+    };
+    if done == 0 {
+        return Vec::new();
+    }
+    // e.g. `+nightly` to choose the toolchain
+    let words: Vec<&String> = args[1..done]
+        .iter()
+        .filter(|x| !x.starts_with('+'))
+        .collect();
+    if words.is_empty() {
+        if word.starts_with('-') {
+            return Vec::new();
+        }
+        return to_completions(get_subcommands(), "", word);
+    }
+
+    let dir = match find_package_dir() {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    let entries = read_manifest(&dir).unwrap_or_default();
+    let prev = words[words.len() - 1].as_str();
+    match prev {
+        "--bin" | "--example" | "--test" | "--bench" => {
+            let names = get_targets(&dir, &entries, &prev[2..]);
+            to_completions(names, "", word)
+        }
+        "--features" | "-F" => {
+            let names = entries
+                .iter()
+                .filter(|x| x.0 == "features" && x.1 != "default")
+                .map(|x| x.1.clone())
+                .collect();
+            // a list like `a,b` is completed at its last item
+            let head = match word.rfind(',') {
+                Some(i) => &word[..i + 1],
+                None => "",
+            };
+            to_completions(names, head, &word[head.len()..])
+        }
+        "-p" | "--package" => to_completions(get_packages(&dir), "", word),
+        _ => Vec::new(),
+    }
+}
+
+fn to_completions(names: BTreeSet<String>, head: &str, word: &str) -> Vec<Completion> {
+    names
+        .into_iter()
+        .filter(|x| x.starts_with(word))
+        .map(|x| Completion {
+            completion: format!("{}{}", head, x),
+            display: None,
+            suffix: Suffix::Default,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_packages;
+    use super::get_targets;
+    use super::read_manifest;
+    use libs::test_dir::TestDir;
+
+    #[test]
+    fn test_cargo_manifest() {
+        let dir = TestDir::new("cargo");
+        dir.write("src/main.rs", "");
+        dir.write("src/bin/extra.rs", "");
+        dir.write("src/bin/tool/main.rs", "");
+        dir.write("examples/demo.rs", "");
+        let manifest =
+            "[package]\nname = \"app\"\n\n[workspace]\nmembers = [\n    \"crates/*\",\n]\n\n\
+                        [features]\ndefault = [\"fast\"]\nfast = []\n\"serde-1\" = [\"serde\"]\n\n\
+                        [[bin]]\nname = \"other\"\npath = \"src/other.rs\"\n";
+        dir.write("Cargo.toml", manifest);
+        dir.write("crates/util/Cargo.toml", "[package]\nname = \"util\"\n");
+
+        let entries = read_manifest(dir.path()).unwrap();
+        let bins: Vec<String> = get_targets(dir.path(), &entries, "bin").into_iter().collect();
+        assert_eq!(bins, vec!["app", "extra", "other", "tool"]);
+        let examples: Vec<String> = get_targets(dir.path(), &entries, "example")
+            .into_iter()
+            .collect();
+        assert_eq!(examples, vec!["demo"]);
+        let features: Vec<&str> = entries
+            .iter()
+            .filter(|x| x.0 == "features")
+            .map(|x| x.1.as_str())
+            .collect();
+        assert_eq!(features, vec!["default", "fast", "serde-1"]);
+        let packages: Vec<String> = get_packages(&dir.join("crates/util"))
+            .into_iter()
+            .collect();
+        assert_eq!(packages, vec!["app", "util"]);
+    }
+}
@@ -1,12 +1,16 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use linefeed::complete::escape;
 use linefeed::complete::escaped_word_start;
 use linefeed::complete::unescape;
-use linefeed::complete::Suffix;
 use linefeed::complete::{Completer, Completion};
 use linefeed::prompter::Prompter;
 use linefeed::terminal::Terminal;
@@ -14,9 +18,19 @@ use linefeed::terminal::Terminal;
 use yaml_rust::yaml;
 use yaml_rust::YamlLoader;
 
+use completers::path;
 use parsers;
 use tools;
 
+/// Milliseconds to wait for the command of a dynamic source.
+const DEFAULT_TIMEOUT: u64 = 500;
+/// Seconds to keep the outputs of a dynamic source.
+const DEFAULT_CACHE: u64 = 10;
+
+/// Outputs of dynamic sources by their command and dir.
+type SourceCache = HashMap<(String, String), (Instant, Vec<String>)>;
+static CACHE: Mutex<Option<SourceCache>> = Mutex::new(None);
+
 /// Performs completion by searching dotfiles
 pub struct DotsCompleter;
 
@@ -45,8 +59,209 @@ impl<Term: Terminal> Completer<Term> for DotsCompleter {
     }
 }
 
-fn complete_dots(line: &str, word: &str) -> Vec<Completion> {
+/// What the words after an item can be.
+#[derive(Debug, PartialEq)]
+enum Args {
+    None,
+    File,
+    Dir,
+    /// one of the given items
+    Enum(Vec<Item>),
+    /// the output lines of a command
+    Run {
+        cmd: String,
+        timeout: u64,
+        cache: u64,
+    },
+}
+
+/// A subcommand or an option in a completion file.
+#[derive(Debug, PartialEq)]
+struct Item {
+    name: String,
+    desc: String,
+    items: Vec<Item>,
+    args: Args,
+}
+
+impl Item {
+    fn new(name: &str) -> Item {
+        Item {
+            name: name.to_string(),
+            desc: String::new(),
+            items: Vec::new(),
+            args: Args::None,
+        }
+    }
+
+    fn is_option(&self) -> bool {
+        self.name.starts_with('-')
+    }
+}
+
+fn scalar(y: &yaml::Yaml) -> Option<String> {
+    match *y {
+        yaml::Yaml::String(ref x) => Some(x.clone()),
+        yaml::Yaml::Integer(x) => Some(x.to_string()),
+        yaml::Yaml::Real(ref x) => Some(x.clone()),
+        yaml::Yaml::Boolean(x) => Some(x.to_string()),
+        _ => None,
+    }
+}
+
+/// Read a list of items. Each of them is one of:
+///
+/// - a plain name: `- install`
+/// - a name with its items: `- install: [--user, -U]`
+/// - a full form: a hash with `name`, and optional `desc`, `items`
+///   and `args`
+fn parse_items(y: &yaml::Yaml) -> Vec<Item> {
+    let mut items = Vec::new();
+    let list = match y.as_vec() {
+        Some(x) => x,
+        None => return items,
+    };
+    for x in list {
+        if let Some(name) = scalar(x) {
+            items.push(Item::new(&name));
+            continue;
+        }
+        let h = match x.as_hash() {
+            Some(h) => h,
+            None => continue,
+        };
+        if let Some(name) = scalar(&x["name"]) {
+            let mut item = Item::new(&name);
+            item.desc = scalar(&x["desc"]).unwrap_or_default();
+            item.items = parse_items(&x["items"]);
+            item.args = parse_args(&x["args"]);
+            items.push(item);
+            continue;
+        }
+        for (k, v) in h.iter() {
+            if let Some(name) = scalar(k) {
+                let mut item = Item::new(&name);
+                item.items = parse_items(v);
+                items.push(item);
+            }
+        }
+    }
+    items
+}
+
+/// `args` is `file`, `dir`, a list of values, or a hash with the
+/// command to `run`, its `timeout` in milliseconds and how many seconds
+/// to `cache` its outputs.
+fn parse_args(y: &yaml::Yaml) -> Args {
+    if let Some(x) = y.as_str() {
+        return match x {
+            "file" => Args::File,
+            "dir" => Args::Dir,
+            _ => Args::None,
+        };
+    }
+    if y.as_vec().is_some() {
+        return Args::Enum(parse_items(y));
+    }
+    if let Some(cmd) = y["run"].as_str() {
+        let number = |key: &str, default: u64| match y[key].as_i64() {
+            Some(x) if x >= 0 => x as u64,
+            _ => default,
+        };
+        return Args::Run {
+            cmd: cmd.to_string(),
+            timeout: number("timeout", DEFAULT_TIMEOUT),
+            cache: number("cache", DEFAULT_CACHE),
+        };
+    }
+    Args::None
+}
+
+/// Find the item whose items or args are to be completed after the
+/// given words. Subcommands are walked into. Options take one word as
+/// their argument if they have `args`. Other words are skipped.
+fn find_item<'a>(root: &'a Item, words: &[String]) -> &'a Item {
+    let mut node = root;
+    let mut pending: Option<&Item> = None;
+    for word in words {
+        if pending.take().is_some() {
+            continue;
+        }
+        if let Some(item) = node.items.iter().find(|x| &x.name == word) {
+            if !item.is_option() {
+                node = item;
+            } else if item.args != Args::None {
+                pending = Some(item);
+            }
+        }
+    }
+    pending.unwrap_or(node)
+}
+
+fn run_source(cmd: &str, timeout: u64, cache: u64) -> Vec<String> {
+    let cwd = match env::current_dir() {
+        Ok(x) => x.to_string_lossy().into_owned(),
+        Err(_) => String::new(),
+    };
+    let key = (cmd.to_string(), cwd);
+    if let Ok(mut guard) = CACHE.lock() {
+        let map = guard.get_or_insert_with(HashMap::new);
+        if let Some((t, lines)) = map.get(&key) {
+            if t.elapsed() < Duration::from_secs(cache) {
+                return lines.clone();
+            }
+        }
+    }
+
+    let output = tools::run_with_timeout(Command::new("sh").arg("-c").arg(cmd), timeout);
+    let lines: Vec<String> = output
+        .unwrap_or_default()
+        .lines()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect();
+    if let Ok(mut guard) = CACHE.lock() {
+        let map = guard.get_or_insert_with(HashMap::new);
+        map.insert(key, (Instant::now(), lines.clone()));
+    }
+    lines
+}
+
+fn complete_item(item: &Item, line: &str, word: &str) -> Vec<Completion> {
     let mut res = Vec::new();
+    for x in &item.items {
+        if x.name.starts_with(word) {
+            res.push(super::to_completion(&x.name, &x.desc));
+        }
+    }
+    match item.args {
+        Args::None => {}
+        Args::File => res.extend(path::complete_path(line, false)),
+        Args::Dir => res.extend(path::complete_path(line, true)),
+        Args::Enum(ref values) => {
+            for x in values {
+                if x.name.starts_with(word) {
+                    res.push(super::to_completion(&x.name, &x.desc));
+                }
+            }
+        }
+        Args::Run {
+            ref cmd,
+            timeout,
+            cache,
+        } => {
+            for x in run_source(cmd, timeout, cache) {
+                if x.starts_with(word) {
+                    res.push(super::to_completion(&x, ""));
+                }
+            }
+        }
+    }
+    res
+}
+
+fn complete_dots(line: &str, word: &str) -> Vec<Completion> {
+    let res = Vec::new();
     let args = parsers::parser_line::line_to_plain_tokens(line);
     if args.is_empty() {
         return res;
@@ -57,13 +272,6 @@ fn complete_dots(line: &str, word: &str) -> Vec<Completion> {
     if !Path::new(dot_file).exists() {
         return res;
     }
-    let sub_cmd = if (args.len() >= 3 && !args[1].starts_with('-'))
-        || (args.len() >= 2 && !args[1].starts_with('-') && line.ends_with(' '))
-    {
-        args[1].as_str()
-    } else {
-        ""
-    };
 
     let mut f;
     match File::open(dot_file) {
@@ -92,72 +300,74 @@ fn complete_dots(line: &str, word: &str) -> Vec<Completion> {
             return res;
         }
     }
+    let mut root = Item::new(&args[0]);
     for doc in &docs {
-        match *doc {
-            yaml::Yaml::Array(ref v) => {
-                for x in v {
-                    match *x {
-                        yaml::Yaml::String(ref name) => {
-                            if sub_cmd != "" || !name.starts_with(word) {
-                                continue;
-                            }
-
-                            let display = None;
-                            let suffix = Suffix::Default;
-                            res.push(Completion {
-                                completion: name.to_string(),
-                                display,
-                                suffix,
-                            });
-                        }
-                        yaml::Yaml::Hash(ref h) => {
-                            for (k, v) in h.iter() {
-                                if let yaml::Yaml::String(ref name) = *k {
-                                    if sub_cmd != "" && sub_cmd != name {
-                                        continue;
-                                    }
-                                    if sub_cmd == "" {
-                                        if !name.starts_with(word) {
-                                            continue;
-                                        }
-
-                                        let name = name.clone();
-                                        let display = None;
-                                        let suffix = Suffix::Default;
-                                        res.push(Completion {
-                                            completion: name,
-                                            display,
-                                            suffix,
-                                        });
-                                    } else if let yaml::Yaml::Array(ref v) = *v {
-                                        for x in v {
-                                            if let yaml::Yaml::String(ref name) = *x {
-                                                if !name.starts_with(word) {
-                                                    continue;
-                                                }
-
-                                                let name = name.clone();
-                                                let display = None;
-                                                let suffix = Suffix::Default;
-                                                res.push(Completion {
-                                                    completion: name,
-                                                    display,
-                                                    suffix,
-                                                });
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            _ => {
-                println!("Found unknown yaml doc");
-            }
-        }
+        root.items.extend(parse_items(doc));
+    }
+
+    // the words typed before the one being completed
+    let done = if line.ends_with(' ') {
+        args.len()
+    } else {
+        args.len() - 1
+    };
+    let item = find_item(&root, &args[1..done.max(1)]);
+    complete_item(item, line, word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_item() {
+        let s = "
+- install:
+    - --user
+- name: checkout
+  desc: Switch branches
+  items:
+    - name: -b
+      args: [main, dev]
+    - --force
+  args:
+    run: echo
+- name: log
+  items:
+    - name: --stat
+      desc: Show stats
+";
+        let docs = YamlLoader::load_from_str(s).unwrap();
+        let mut root = Item::new("git");
+        root.items = parse_items(&docs[0]);
+        assert_eq!(root.items.len(), 3);
+        assert_eq!(root.items[1].desc, "Switch branches");
+
+        let words = |x: &str| -> Vec<String> { x.split(' ').map(|x| x.to_string()).collect() };
+        assert_eq!(find_item(&root, &words("install")).name, "install");
+        assert_eq!(
+            find_item(&root, &words("checkout --force")).name,
+            "checkout"
+        );
+        assert_eq!(find_item(&root, &words("checkout -b")).name, "-b");
+        assert_eq!(find_item(&root, &words("checkout -b x")).name, "checkout");
+        assert_eq!(find_item(&root, &words("foo")).name, "git");
+
+        let item = find_item(&root, &words("checkout -b"));
+        assert_eq!(
+            item.args,
+            Args::Enum(vec![Item::new("main"), Item::new("dev")])
+        );
+        let item = find_item(&root, &words("checkout"));
+        let expected = Args::Run {
+            cmd: "echo".to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            cache: DEFAULT_CACHE,
+        };
+        assert_eq!(item.args, expected);
+
+        let item = find_item(&root, &words("log"));
+        let res = complete_item(item, "git log --s", "--s");
+        assert_eq!(res[0].display, Some("--stat -- Show stats".to_string()));
     }
-    res
 }
@@ -0,0 +1,108 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::sync::Arc;
+
+use linefeed::complete::{Completer, Completion, Suffix};
+use linefeed::prompter::Prompter;
+use linefeed::terminal::Terminal;
+
+use shell;
+
+/// Special parameters, see `shell::get_special_param()`.
+const SPECIAL_PARAMS: &[&str] = &[
+    "?", "$", "!", "#", "@", "*", "-", "0", "LINENO", "PPID", "RANDOM", "SECONDS",
+];
+
+pub struct EnvCompleter {
+    pub sh: Arc<shell::Shell>,
+}
+
+impl<Term: Terminal> Completer<Term> for EnvCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        _reader: &Prompter<Term>,
+        _start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        Some(complete_env(&self.sh, word))
+    }
+}
+
+/// Complete variable names of words like `$HO`, `${HO` or `"$HO`, and
+/// plain names like `HO` for the arguments of `export` and `unset`.
+/// The brace form gets its brace closed.
+pub fn complete_env(sh: &shell::Shell, word: &str) -> Vec<Completion> {
+    let (head, brace, prefix) = match word.rfind('$') {
+        Some(i) => {
+            let rest = &word[i + 1..];
+            match rest.strip_prefix('{') {
+                Some(x) => (&word[..i + 1], true, x),
+                None => (&word[..i + 1], false, rest),
+            }
+        }
+        None => ("", false, word),
+    };
+    let with_dollar = !head.is_empty();
+
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    for (name, _) in env::vars() {
+        names.insert(name);
+    }
+    for name in sh.envs.keys() {
+        names.insert(name.clone());
+    }
+    if with_dollar {
+        for name in SPECIAL_PARAMS {
+            names.insert(name.to_string());
+        }
+    }
+
+    let mut res = Vec::new();
+    for name in names {
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        let completion = if brace {
+            format!("{}{{{}}}", head, name)
+        } else {
+            format!("{}{}", head, name)
+        };
+        res.push(Completion {
+            completion,
+            display: None,
+            suffix: Suffix::Default,
+        });
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::complete_env;
+    use shell::Shell;
+
+    fn complete(sh: &Shell, word: &str) -> Vec<String> {
+        complete_env(sh, word)
+            .into_iter()
+            .map(|x| x.completion)
+            .collect()
+    }
+
+    #[test]
+    fn test_complete_env() {
+        let mut sh = Shell::new();
+        sh.set_env("CICADA_TEST_VAR_X", "1").unwrap();
+        assert_eq!(
+            complete(&sh, "$CICADA_TEST_VAR"),
+            vec!["$CICADA_TEST_VAR_X"]
+        );
+        assert_eq!(
+            complete(&sh, "\"a${CICADA_TEST_VAR"),
+            vec!["\"a${CICADA_TEST_VAR_X}"]
+        );
+        assert_eq!(complete(&sh, "CICADA_TEST_VAR"), vec!["CICADA_TEST_VAR_X"]);
+        assert_eq!(complete(&sh, "$RAN"), vec!["$RANDOM"]);
+        assert!(complete(&sh, "RAN").is_empty());
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use linefeed::complete::{Completer, Completion, Suffix};
+use linefeed::prompter::Prompter;
+use linefeed::terminal::Terminal;
+
+use libs;
+use parsers;
+use tools;
+
+/// Milliseconds to wait for `git status` when completing changed files.
+const STATUS_TIMEOUT: u64 = 500;
+
+const SUBCOMMANDS: &[&str] = &[
+    "add",
+    "am",
+    "bisect",
+    "blame",
+    "branch",
+    "checkout",
+    "cherry-pick",
+    "clean",
+    "clone",
+    "commit",
+    "config",
+    "describe",
+    "diff",
+    "fetch",
+    "grep",
+    "init",
+    "log",
+    "merge",
+    "mv",
+    "pull",
+    "push",
+    "rebase",
+    "reflog",
+    "remote",
+    "reset",
+    "restore",
+    "revert",
+    "rm",
+    "show",
+    "stash",
+    "status",
+    "submodule",
+    "switch",
+    "tag",
+    "worktree",
+];
+
+pub struct GitCompleter;
+
+impl<Term: Terminal> Completer<Term> for GitCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        reader: &Prompter<Term>,
+        _start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        Some(complete_git(reader.buffer(), word))
+    }
+}
+
+/// The dir with the refs and the config of the repo; for worktrees
+/// it is the one of the main repo.
+fn get_common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(x) => git_dir.join(x.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+/// Names of the refs under `refs/<kind>/`, both loose and packed.
+fn read_refs(common_dir: &Path, kind: &str) -> BTreeSet<String> {
+    let mut refs = BTreeSet::new();
+    let prefix = format!("refs/{}/", kind);
+    collect_refs(&common_dir.join(&prefix), "", &mut refs);
+    if let Ok(packed) = fs::read_to_string(common_dir.join("packed-refs")) {
+        for line in packed.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some(name) = line.split_whitespace().nth(1) {
+                if let Some(x) = name.strip_prefix(&prefix) {
+                    refs.insert(x.to_string());
+                }
+            }
+        }
+    }
+    refs
+}
+
+fn collect_refs(dir: &Path, prefix: &str, refs: &mut BTreeSet<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if path.is_dir() {
+            collect_refs(&path, &format!("{}{}/", prefix, name), refs);
+        } else if name != "HEAD" {
+            refs.insert(format!("{}{}", prefix, name));
+        }
+    }
+}
+
+/// Read the `[remote "name"]` sections and the aliases of git config
+/// files.
+fn parse_config(
+    content: &str,
+    remotes: &mut BTreeSet<String>,
+    aliases: &mut Vec<(String, String)>,
+) {
+    let mut section = String::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            section = line.trim_matches(|c| c == '[' || c == ']').to_string();
+            if let Some(x) = section.strip_prefix("remote ") {
+                remotes.insert(x.trim().trim_matches('"').to_string());
+            }
+            continue;
+        }
+        if section.to_lowercase() != "alias" || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim().trim_matches('"');
+        if !name.is_empty() {
+            aliases.push((name.to_string(), value.to_string()));
+        }
+    }
+}
+
+struct Repo {
+    common_dir: Option<PathBuf>,
+    remotes: BTreeSet<String>,
+    aliases: Vec<(String, String)>,
+}
+
+fn load_repo() -> Repo {
+    let mut repo = Repo {
+        common_dir: None,
+        remotes: BTreeSet::new(),
+        aliases: Vec::new(),
+    };
+    let home = tools::get_user_home();
+    let mut configs = vec![Path::new(&home).join(".gitconfig")];
+    if let Ok(cwd) = env::current_dir() {
+        if let Some(git_dir) = libs::git::find_git_dir(&cwd) {
+            let common_dir = get_common_dir(&git_dir);
+            configs.push(common_dir.join("config"));
+            repo.common_dir = Some(common_dir);
+        }
+    }
+    for file in configs {
+        if let Ok(content) = fs::read_to_string(file) {
+            parse_config(&content, &mut repo.remotes, &mut repo.aliases);
+        }
+    }
+    repo
+}
+
+impl Repo {
+    fn refs(&self, kind: &str) -> BTreeSet<String> {
+        match self.common_dir {
+            Some(ref x) => read_refs(x, kind),
+            None => BTreeSet::new(),
+        }
+    }
+
+    /// Remote branches without the remote names, e.g. `dev` for
+    /// `origin/dev`, which `checkout` and `switch` take too.
+    fn remote_branch_names(&self) -> BTreeSet<String> {
+        self.refs("remotes")
+            .into_iter()
+            .filter_map(|x| x.split_once('/').map(|x| x.1.to_string()))
+            .filter(|x| x != "HEAD")
+            .collect()
+    }
+}
+
+/// Files shown by `git status`: the ones changed in the work tree and
+/// the untracked ones if `worktree`, otherwise the staged ones. Paths
+/// are relative to the current dir.
+fn changed_files(worktree: bool) -> Vec<String> {
+    let mut cmd = Command::new("git");
+    cmd.args([
+        "-c",
+        "color.status=false",
+        "-c",
+        "status.relativePaths=true",
+        "status",
+        "--short",
+        "--untracked-files=all",
+    ]);
+    let output = tools::run_with_timeout(&mut cmd, STATUS_TIMEOUT).unwrap_or_default();
+    parse_short_status(&output, worktree)
+}
+
+fn parse_short_status(output: &str, worktree: bool) -> Vec<String> {
+    let mut files = Vec::new();
+    for line in output.lines() {
+        if line.len() < 4 {
+            continue;
+        }
+        let (x, y) = (line.as_bytes()[0], line.as_bytes()[1]);
+        let changed = if worktree {
+            y != b' '
+        } else {
+            x != b' ' && x != b'?'
+        };
+        if !changed {
+            continue;
+        }
+        let path = &line[3..];
+        let path = match path.find(" -> ") {
+            Some(i) => &path[i + 4..],
+            None => path,
+        };
+        files.push(path.trim_matches('"').to_string());
+    }
+    files
+}
+
+fn complete_git(line: &str, word: &str) -> Vec<Completion> {
+    let args = parsers::parser_line::line_to_plain_tokens(line);
+    let done = if line.ends_with(' ') {
+        args.len()
+    } else {
+        args.len().saturating_sub(1)
+    };
+    let words = if done > 1 { &args[1..done] } else { &[] };
+
+    // skip the options of git itself, `-C` and `-c` take an argument
+    let mut i = 0;
+    while i < words.len() && words[i].starts_with('-') {
+        i += if words[i] == "-C" || words[i] == "-c" {
+            2
+        } else {
+            1
+        };
+    }
+
+    let repo = load_repo();
+    if i >= words.len() {
+        let mut names: BTreeSet<String> = SUBCOMMANDS.iter().map(|x| x.to_string()).collect();
+        names.extend(repo.aliases.iter().map(|x| x.0.clone()));
+        return to_completions(names, word);
+    }
+    if word.starts_with('-') {
+        return Vec::new();
+    }
+
+    let mut subcmd = words[i].clone();
+    if let Some((_, value)) = repo.aliases.iter().find(|x| x.0 == subcmd) {
+        if let Some(x) = value.split_whitespace().next() {
+            subcmd = x.to_string();
+        }
+    }
+    let rest = &words[i + 1..];
+    let nth = rest.iter().filter(|x| !x.starts_with('-')).count();
+
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    match subcmd.as_str() {
+        "checkout" => {
+            names.extend(repo.refs("heads"));
+            names.extend(repo.remote_branch_names());
+            names.extend(repo.refs("tags"));
+        }
+        "switch" => {
+            names.extend(repo.refs("heads"));
+            names.extend(repo.remote_branch_names());
+        }
+        "merge" | "rebase" | "cherry-pick" | "log" | "reset" => {
+            names.extend(repo.refs("heads"));
+            names.extend(repo.refs("remotes"));
+            names.extend(repo.refs("tags"));
+        }
+        "branch" => names.extend(repo.refs("heads")),
+        "tag" => names.extend(repo.refs("tags")),
+        "push" | "fetch" | "pull" => {
+            if nth == 0 {
+                names.extend(repo.remotes.iter().cloned());
+            } else {
+                names.extend(repo.refs("heads"));
+            }
+        }
+        "add" => names.extend(changed_files(true)),
+        "restore" => {
+            let staged = rest.iter().any(|x| x == "--staged" || x == "-S");
+            names.extend(changed_files(!staged));
+        }
+        "diff" => names.extend(changed_files(true)),
+        _ => {}
+    }
+    to_completions(names, word)
+}
+
+fn to_completions(names: BTreeSet<String>, word: &str) -> Vec<Completion> {
+    names
+        .into_iter()
+        .filter(|x| x.starts_with(word))
+        .map(|x| Completion {
+            completion: x,
+            display: None,
+            suffix: Suffix::Default,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::parse_config;
+    use super::parse_short_status;
+    use super::read_refs;
+    use libs::test_dir::TestDir;
+
+    #[test]
+    fn test_git_refs_and_config() {
+        let dir = TestDir::new("git-refs");
+        dir.write("refs/heads/master", "abc\n");
+        dir.write("refs/heads/feature/x", "abc\n");
+        dir.write(
+            "packed-refs",
+            "# pack-refs with: peeled\nabc refs/heads/old\nabc refs/tags/v1.0\n^def\n",
+        );
+        let heads: Vec<String> = read_refs(dir.path(), "heads").into_iter().collect();
+        assert_eq!(heads, vec!["feature/x", "master", "old"]);
+        let tags: Vec<String> = read_refs(dir.path(), "tags").into_iter().collect();
+        assert_eq!(tags, vec!["v1.0"]);
+
+        let config = "[core]\n\tbare = false\n[remote \"origin\"]\n\turl = x\n\
+                      [alias]\n\tco = checkout\n\tst = status -s\n";
+        let mut remotes = BTreeSet::new();
+        let mut aliases = Vec::new();
+        parse_config(config, &mut remotes, &mut aliases);
+        assert!(remotes.contains("origin"));
+        assert_eq!(aliases[1], ("st".to_string(), "status -s".to_string()));
+
+        let output = " M src/a.rs\nM  b.rs\n?? c.txt\nR  d.rs -> e.rs\n";
+        assert_eq!(parse_short_status(output, true), vec!["src/a.rs", "c.txt"]);
+        assert_eq!(parse_short_status(output, false), vec!["b.rs", "e.rs"]);
+    }
+}
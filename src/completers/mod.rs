@@ -1,14 +1,18 @@
 use std::path::Path;
 use std::sync::Arc;
 
-use linefeed::complete::{Completer, Completion};
+use linefeed::complete::{Completer, Completion, Suffix};
 use linefeed::prompter::Prompter;
 use linefeed::terminal::Terminal;
 use regex::Regex;
 
+pub mod cargo;
 pub mod dots;
+pub mod env;
+pub mod git;
 pub mod make;
 pub mod path;
+pub mod plugin;
 pub mod ssh;
 
 use parsers;
@@ -23,14 +27,33 @@ fn for_make(line: &str) -> bool {
     tools::re_contains(line, r"^ *make ")
 }
 
+fn for_cargo(line: &str) -> bool {
+    tools::re_contains(line, r"^ *cargo +")
+}
+
 fn for_ssh(line: &str) -> bool {
     tools::re_contains(line, r"^ *(ssh|scp).* +[^ \./]+ *$")
 }
 
+fn for_git(line: &str) -> bool {
+    tools::re_contains(line, r"^ *git +")
+}
+
 fn for_cd(line: &str) -> bool {
     tools::re_contains(line, r"^ *cd +")
 }
 
+/// Words like `$HO` and `${HO`.
+fn for_env(word: &str) -> bool {
+    tools::re_contains(word, r"\$\{?[a-zA-Z0-9_]*$")
+}
+
+/// Variable names as the arguments of `export` and `unset`.
+fn for_env_names(line: &str, word: &str) -> bool {
+    tools::re_contains(line, r"^ *(export|unset) +")
+        && tools::re_contains(word, r"^[a-zA-Z0-9_]*$")
+}
+
 fn for_bin(line: &str) -> bool {
     let re;
     if let Ok(x) = Regex::new(r"(^ *[a-zA-Z0-9_\.-]+$)|(^.+\| +[a-zA-Z0-9_\.-]+$)") {
@@ -41,6 +64,15 @@ fn for_bin(line: &str) -> bool {
     re.is_match(line)
 }
 
+/// Whether there is an executable completer for the command.
+fn for_plugin(line: &str) -> bool {
+    let args = parsers::parser_line::line_to_plain_tokens(line);
+    if args.is_empty() {
+        return false;
+    }
+    plugin::find_plugin(&args[0]).is_some()
+}
+
 fn for_dots(line: &str) -> bool {
     let args = parsers::parser_line::line_to_plain_tokens(line);
     let len = args.len();
@@ -63,6 +95,12 @@ impl<Term: Terminal> Completer<Term> for CicadaCompleter {
         let line = reader.buffer();
 
         // these completions should not fail back to path completion.
+        if for_env(word) || for_env_names(line, word) {
+            let cpl = Arc::new(env::EnvCompleter {
+                sh: self.sh.clone(),
+            });
+            return cpl.complete(word, reader, start, _end);
+        }
         if for_bin(line) {
             let cpl = Arc::new(path::BinCompleter {
                 sh: self.sh.clone(),
@@ -92,6 +130,14 @@ impl<Term: Terminal> Completer<Term> for CicadaCompleter {
                 }
             }
         }
+        if for_plugin(line) {
+            let cpl = Arc::new(plugin::PluginCompleter);
+            if let Some(x) = cpl.complete(word, reader, start, _end) {
+                if !x.is_empty() {
+                    return Some(x);
+                }
+            }
+        }
         if for_dots(line) {
             let cpl = Arc::new(dots::DotsCompleter);
             if let Some(x) = cpl.complete(word, reader, start, _end) {
@@ -100,6 +146,24 @@ impl<Term: Terminal> Completer<Term> for CicadaCompleter {
                 }
             }
         }
+        // the built-in git and cargo completions come after the plugin
+        // and dots ones, so that a completer of the user for them wins.
+        if for_cargo(line) {
+            let cpl = Arc::new(cargo::CargoCompleter);
+            if let Some(x) = cpl.complete(word, reader, start, _end) {
+                if !x.is_empty() {
+                    return Some(x);
+                }
+            }
+        }
+        if for_git(line) {
+            let cpl = Arc::new(git::GitCompleter);
+            if let Some(x) = cpl.complete(word, reader, start, _end) {
+                if !x.is_empty() {
+                    return Some(x);
+                }
+            }
+        }
 
         let cpl = Arc::new(path::PathCompleter);
         cpl.complete(word, reader, start, _end)
@@ -110,6 +174,20 @@ impl<Term: Terminal> Completer<Term> for CicadaCompleter {
     }
 }
 
+/// A candidate, shown with its description in the menu if there is one.
+pub fn to_completion(name: &str, desc: &str) -> Completion {
+    let display = if desc.is_empty() {
+        None
+    } else {
+        Some(format!("{} -- {}", name, desc))
+    };
+    Completion {
+        completion: name.to_string(),
+        display,
+        suffix: Suffix::Default,
+    }
+}
+
 /// via: https://github.com/murarth/linefeed/blob/master/src/complete.rs
 /// Returns the start position of a word with non-word characters escaped by
 /// backslash (`\\`).
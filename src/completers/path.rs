@@ -1,16 +1,17 @@
 use std::collections::HashSet;
 use std::env;
-use std::fs::read_dir;
+use std::fs::{metadata, read_dir, Metadata};
 use std::io::Write;
 use std::iter::FromIterator;
 use std::os::unix::fs::PermissionsExt;
-use std::path::{is_separator, MAIN_SEPARATOR};
+use std::path::{is_separator, Path, MAIN_SEPARATOR};
 use std::sync::Arc;
 
 use linefeed::complete::{Completer, Completion, Suffix};
 use linefeed::terminal::Terminal;
 use linefeed::Prompter;
 
+use builtins;
 use parsers;
 use shell;
 use tools;
@@ -29,12 +30,7 @@ impl<Term: Terminal> Completer<Term> for BinCompleter {
         _start: usize,
         _end: usize,
     ) -> Option<Vec<Completion>> {
-        // TODO: use RC::into_raw() instead
-        let sh = Arc::try_unwrap(self.sh.clone());
-        match sh {
-            Ok(x) => Some(complete_bin(&x, word)),
-            Err(e) => Some(complete_bin(&e, word)),
-        }
+        Some(complete_bin(&self.sh, word))
     }
 }
 
@@ -138,6 +134,22 @@ fn split_path(path: &str) -> (Option<&str>, &str) {
     }
 }
 
+pub fn is_executable(meta: &Metadata) -> bool {
+    meta.permissions().mode() & 0o111 != 0
+}
+
+/// Whether `name` is an executable file in one of the dirs in `$PATH`.
+pub fn is_in_path(name: &str) -> bool {
+    let env_path = match env::var("PATH") {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    env_path.split(':').any(|dir| match metadata(Path::new(dir).join(name)) {
+        Ok(x) => x.is_file() && is_executable(&x),
+        Err(_) => false,
+    })
+}
+
 /// Returns a sorted list of paths whose prefix matches the given path.
 fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
     let mut res = Vec::new();
@@ -151,24 +163,22 @@ fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
         }
     }
 
-    // handle alias and builtins
-    for alias in sh.alias.keys() {
-        if !alias.starts_with(fname) {
-            continue;
-        }
-        res.push(Completion {
-            completion: alias.to_owned(),
-            display: None,
-            suffix: Suffix::Default,
-        });
-    }
-    let builtins = vec!["cd", "cinfo", "exec", "exit", "export", "history", "vox"];
-    for item in &builtins {
-        if !item.starts_with(fname) {
+    // handle alias, functions and builtins
+    let mut checker: HashSet<String> = HashSet::new();
+    let names = sh
+        .alias
+        .keys()
+        .chain(sh.funcs.keys())
+        .map(|x| x.as_str())
+        .chain(builtins::BUILTINS.iter().map(|x| x.0))
+        .filter(|x| x.starts_with(char::is_alphabetic));
+    for name in names {
+        if !name.starts_with(fname) || checker.contains(name) {
             continue;
         }
+        checker.insert(name.to_string());
         res.push(Completion {
-            completion: item.to_string(),
+            completion: name.to_string(),
             display: None,
             suffix: Suffix::Default,
         });
@@ -177,7 +187,6 @@ fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
     let vec_path: Vec<&str> = env_path.split(':').collect();
     let path_list: HashSet<&str> = HashSet::from_iter(vec_path.iter().cloned());
 
-    let mut checker: HashSet<String> = HashSet::new();
     for p in &path_list {
         if let Ok(list) = read_dir(p) {
             for entry in list {
@@ -192,8 +201,7 @@ fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
                                     continue;
                                 }
                             }
-                            let mode = _mode.permissions().mode();
-                            if mode & 0o111 == 0 {
+                            if !is_executable(&_mode) {
                                 // not binary
                                 continue;
                             }
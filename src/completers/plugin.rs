@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use linefeed::complete::{Completer, Completion};
+use linefeed::prompter::Prompter;
+use linefeed::terminal::Terminal;
+
+use completers::path;
+use parsers;
+use tools;
+
+/// Milliseconds to wait for an executable completer.
+const TIMEOUT: u64 = 1000;
+
+/// Performs completion by running an executable in the completer dir
+/// named after the command, e.g. `~/.cicada/completers/kubectl`.
+pub struct PluginCompleter;
+
+impl<Term: Terminal> Completer<Term> for PluginCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        reader: &Prompter<Term>,
+        _start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        Some(complete_plugin(reader.buffer(), reader.cursor(), word))
+    }
+}
+
+/// The executable completer of the command, if there is one.
+pub fn find_plugin(cmd: &str) -> Option<PathBuf> {
+    if cmd.is_empty() || cmd.contains('/') {
+        return None;
+    }
+    let file = PathBuf::from(tools::get_user_completer_dir()).join(cmd);
+    let meta = fs::metadata(&file).ok()?;
+    if meta.is_file() && path::is_executable(&meta) {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+/// Run the completer with the line, the cursor position (in bytes) and
+/// the word being completed in `$CICADA_LINE`, `$CICADA_CURSOR` and
+/// `$CICADA_WORD`. Each line of its output is a candidate, optionally
+/// followed by a tab and its description.
+fn complete_plugin(line: &str, cursor: usize, word: &str) -> Vec<Completion> {
+    let args = parsers::parser_line::line_to_plain_tokens(line);
+    let file = match args.first().and_then(|x| find_plugin(x)) {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    let mut command = Command::new(file);
+    command
+        .env("CICADA_LINE", line)
+        .env("CICADA_CURSOR", cursor.to_string())
+        .env("CICADA_WORD", word);
+    match tools::run_with_timeout(&mut command, TIMEOUT) {
+        Some(output) => parse_output(&output),
+        None => Vec::new(),
+    }
+}
+
+fn parse_output(output: &str) -> Vec<Completion> {
+    let mut res = Vec::new();
+    for line in output.lines() {
+        let mut parts = line.splitn(2, '\t');
+        let name = parts.next().unwrap_or("").trim();
+        if name.is_empty() {
+            continue;
+        }
+        let desc = parts.next().unwrap_or("").trim();
+        res.push(super::to_completion(name, desc));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_output;
+
+    #[test]
+    fn test_parse_output() {
+        let res = parse_output("get\tDisplay resources\n\napply\n");
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].completion, "get");
+        assert_eq!(res[0].display, Some("get -- Display resources".to_string()));
+        assert_eq!(res[1].completion, "apply");
+        assert_eq!(res[1].display, None);
+    }
+}
@@ -0,0 +1,269 @@
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use linefeed::terminal::DefaultTerminal;
+use linefeed::{Command, Interface, Prompter};
+
+use highlight;
+use history;
+use libs;
+use shell;
+
+/// The input line the suggestion was looked up for, and the suggestion.
+#[derive(Debug, Default)]
+pub struct EditState {
+    pub buffer: String,
+    /// the rest of a command in history shown after the input
+    pub suggestion: String,
+}
+
+pub type SharedState = Arc<Mutex<EditState>>;
+
+type EditFn = fn(&mut Prompter<DefaultTerminal>) -> io::Result<()>;
+
+/// Bind Right and End to accept the whole suggestion, Alt-F its next
+/// word; with no suggestion they move the cursor as usual. The line is
+/// highlighted by `redraw()` after each step of reading it, whatever key
+/// changed it.
+pub fn init(rl: &Interface<DefaultTerminal>) -> SharedState {
+    let state: SharedState = Arc::new(Mutex::new(EditState::default()));
+
+    define_accept(rl, &state, "cicada-forward-char", true, forward_char);
+    define_accept(rl, &state, "cicada-end-of-line", true, end_of_line);
+    define_accept(rl, &state, "cicada-forward-word", false, forward_word);
+
+    for seq in &["\x1B[C", "\x1BOC", "\x06"] {
+        rl.bind_sequence(*seq, Command::from_str("cicada-forward-char"));
+    }
+    for seq in &["\x1B[F", "\x1BOF", "\x1B[4~", "\x05"] {
+        rl.bind_sequence(*seq, Command::from_str("cicada-end-of-line"));
+    }
+    rl.bind_sequence("\x1Bf", Command::from_str("cicada-forward-word"));
+    state
+}
+
+/// Define a function that accepts the suggestion, the whole of it or
+/// its next word, or does `fallback` if there is none.
+fn define_accept(
+    rl: &Interface<DefaultTerminal>,
+    state: &SharedState,
+    name: &'static str,
+    whole: bool,
+    fallback: EditFn,
+) {
+    let st = state.clone();
+    rl.define_function(
+        name,
+        Arc::new(
+            move |prompter: &mut Prompter<DefaultTerminal>, _count: i32, _ch: char| {
+                match get_accepted(prompter, &st, whole) {
+                    Some(text) => prompter.insert_str(&text),
+                    None => fallback(prompter),
+                }
+            },
+        ),
+    );
+}
+
+/// The part of the suggestion to insert, if one is shown for the
+/// current input.
+fn get_accepted(
+    prompter: &Prompter<DefaultTerminal>,
+    state: &SharedState,
+    whole: bool,
+) -> Option<String> {
+    let st = state.lock().ok()?;
+    if st.suggestion.is_empty()
+        || st.buffer != prompter.buffer()
+        || prompter.cursor() != prompter.buffer().len()
+    {
+        return None;
+    }
+    if whole {
+        return Some(st.suggestion.clone());
+    }
+    Some(next_word(&st.suggestion).to_string())
+}
+
+/// The start of `text` up to the end of its first word.
+fn next_word(text: &str) -> &str {
+    let start = text
+        .find(|c: char| c.is_alphanumeric())
+        .unwrap_or(text.len());
+    let end = text[start..]
+        .find(|c: char| !c.is_alphanumeric())
+        .map_or(text.len(), |x| start + x);
+    &text[..end]
+}
+
+fn forward_char(prompter: &mut Prompter<DefaultTerminal>) -> io::Result<()> {
+    let cursor = prompter.cursor();
+    match prompter.buffer()[cursor..].chars().next() {
+        Some(c) => prompter.set_cursor(cursor + c.len_utf8()),
+        None => Ok(()),
+    }
+}
+
+fn end_of_line(prompter: &mut Prompter<DefaultTerminal>) -> io::Result<()> {
+    let end = prompter.buffer().len();
+    prompter.set_cursor(end)
+}
+
+fn forward_word(prompter: &mut Prompter<DefaultTerminal>) -> io::Result<()> {
+    let cursor = prompter.cursor();
+    let len = next_word(&prompter.buffer()[cursor..]).len();
+    prompter.set_cursor(cursor + len)
+}
+
+/// The most recent successful command starting with the input.
+fn get_suggestion(sh: &shell::Shell, buffer: &str) -> String {
+    if buffer.trim().is_empty() || sh.get_env("AUTOSUGGEST") == Some("0".to_string()) {
+        return String::new();
+    }
+    let cwd = match env::current_dir() {
+        Ok(x) => x.to_string_lossy().into_owned(),
+        Err(_) => String::new(),
+    };
+    match history::suggest(buffer, &cwd) {
+        Some(ref x) if !x.contains('\n') => x.clone(),
+        _ => String::new(),
+    }
+}
+
+/// The width of `text` on screen at most: non-ASCII characters are
+/// taken as two columns wide, as some are.
+fn max_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/// Draw the input line again with colors and the suggestion after it,
+/// once linefeed drew it, then put the cursor back. It's done after
+/// every step of reading the line, thus after any key that changes it.
+/// Only lines that fit in one row are drawn, as their positions on
+/// screen are known for sure.
+pub fn redraw(sh: &shell::Shell, state: &SharedState, prompt: &str, buffer: &str) {
+    let mut st = match state.lock() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let had_suggestion = !st.suggestion.is_empty();
+    let end = input_end(prompt, buffer);
+    let width = libs::prompt::get_term_width();
+    if end >= width {
+        st.buffer = buffer.to_string();
+        st.suggestion.clear();
+        return;
+    }
+    if st.buffer != buffer {
+        st.suggestion = get_suggestion(sh, buffer);
+        st.buffer = buffer.to_string();
+    }
+
+    let highlighting = sh.get_env("HIGHLIGHT") != Some("0".to_string());
+    if !highlighting && st.suggestion.is_empty() && !had_suggestion {
+        return;
+    }
+
+    let start = end - max_width(buffer);
+    let mut out = String::from("\x1B7\r");
+    if start > 0 {
+        out.push_str(&format!("\x1B[{}C", start));
+    }
+    if highlighting {
+        out.push_str(&highlight::highlight(sh, buffer));
+    } else {
+        out.push_str(buffer);
+    }
+    if had_suggestion || !st.suggestion.is_empty() {
+        // only when needed, as it clears the right prompt too
+        out.push_str("\x1B[K");
+    }
+    if !st.suggestion.is_empty() {
+        let mut room = width - end - 1;
+        let mut shown = String::new();
+        for c in st.suggestion.chars() {
+            let w = max_width(c.encode_utf8(&mut [0; 4]));
+            if w > room {
+                break;
+            }
+            room -= w;
+            shown.push(c);
+        }
+        out.push_str(&highlight::paint(sh, "SUGGESTION", &shown));
+    }
+    out.push_str("\x1B8");
+
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// The column the input ends at, as if the last line of the prompt and
+/// the input were on one row.
+pub fn input_end(prompt: &str, buffer: &str) -> usize {
+    let last_line = prompt.rsplit('\n').next().unwrap_or("");
+    libs::prompt::visible_width(last_line) + max_width(buffer)
+}
+
+/// Clear the row from column `col` on, e.g. what is left of the right
+/// prompt after the input, then put the cursor back.
+pub fn clear_line_after(col: usize) {
+    if col >= libs::prompt::get_term_width() {
+        return;
+    }
+    let mut out = String::from("\x1B7\r");
+    if col > 0 {
+        out.push_str(&format!("\x1B[{}C", col));
+    }
+    out.push_str("\x1B[K\x1B8");
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Clear the suggestion left after the line that was just entered, so
+/// that it does not stay on screen.
+pub fn finish(state: &SharedState, prompt: &str, line: &str) {
+    let mut st = match state.lock() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let buffer = mem::take(&mut st.buffer);
+    if st.suggestion.is_empty() {
+        return;
+    }
+    st.suggestion.clear();
+    if buffer != line {
+        return;
+    }
+    let col = input_end(prompt, line);
+    let mut out = String::from("\x1B[A\r");
+    if col > 0 {
+        out.push_str(&format!("\x1B[{}C", col));
+    }
+    out.push_str("\x1B[K\x1B[B\r");
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{max_width, next_word};
+
+    #[test]
+    fn test_next_word() {
+        assert_eq!(next_word("tatus --short"), "tatus");
+        assert_eq!(next_word(" --short -b"), " --short");
+        assert_eq!(next_word("/foo/bar"), "/foo");
+        assert_eq!(next_word("  "), "  ");
+    }
+
+    #[test]
+    fn test_max_width() {
+        assert_eq!(max_width("ls -l"), 5);
+        assert_eq!(max_width("echo 中文"), 9);
+    }
+}
@@ -2,10 +2,11 @@ use std::collections::HashMap;
 use std::error::Error as STDError;
 use std::fs::File;
 use std::io::{self, Error, Read, Write};
-use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::os::unix::process::CommandExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::Path;
 use std::process::{Command, Output, Stdio};
+use std::sync::atomic::Ordering;
 
 use regex::Regex;
 
@@ -16,6 +17,8 @@ use nom::IResult;
 
 use builtins;
 use parsers;
+use parsers::parser_script::ParseError;
+use scripting;
 use shell;
 use tools::{self, clog, CommandResult};
 use types;
@@ -42,7 +45,8 @@ pub fn handle_non_tty(sh: &mut shell::Shell) {
     match handle.read_to_string(&mut buffer) {
         Ok(_) => {
             log!("run non tty command: {}", &buffer);
-            run_procs(sh, &buffer, false);
+            sh.lineno = 1;
+            sh.previous_status = run_procs(sh, &buffer, false);
         }
         Err(e) => {
             println!("cicada: io stdin read_to_string failed: {:?}", e);
@@ -74,7 +78,25 @@ fn tokens_to_cmd_tokens(tokens: &types::Tokens) -> Vec<types::Tokens> {
     cmds
 }
 
+/// Run a script, which could be a single command line or many lines
+/// with compound commands like `if`.
 pub fn run_procs(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
+    let first_lineno = if sh.lineno > 0 { sh.lineno } else { 1 };
+    match parsers::parser_script::parse(line, first_lineno) {
+        Ok(stmts) => scripting::run_stmts(sh, &stmts, tty),
+        Err(ParseError::Incomplete) => {
+            println_stderr!("cicada: syntax error: unexpected end of file");
+            2
+        }
+        Err(ParseError::Syntax(msg, lineno)) => {
+            println_stderr!("cicada: line {}: {}", lineno, msg);
+            2
+        }
+    }
+}
+
+/// Run commands joined with `&&` and `||`, e.g. `make && make install`.
+pub fn run_cmd_list(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
     if tools::is_arithmetic(line) {
         if line.contains('.') {
             match run_calc_float(line) {
@@ -110,15 +132,31 @@ pub fn run_procs(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
             sep = token.clone();
             continue;
         }
+        // a skipped command keeps the status for the next `&&` or `||`
         if sep == "&&" && status != 0 {
-            break;
+            continue;
         }
         if sep == "||" && status == 0 {
-            break;
+            continue;
         }
         let mut cmd = token.clone();
+        let negated = cmd.starts_with("! ");
+        if negated {
+            cmd = cmd[2..].trim().to_string();
+        }
+        if cmd.starts_with("[[ ") {
+            // conditions are expanded by `[[` itself, without globbing
+            status = builtins::test::run_cond(sh, &cmd);
+            if negated {
+                status = if status == 0 { 1 } else { 0 };
+            }
+            continue;
+        }
         tools::pre_handle_cmd_line(&sh, &mut cmd);
         status = run_proc(sh, &cmd, tty);
+        if negated {
+            status = if status == 0 { 1 } else { 0 };
+        }
     }
     status
 }
@@ -156,38 +194,36 @@ fn drain_env_tokens(tokens: &mut Vec<(String, String)>) -> HashMap<String, Strin
 }
 
 pub fn run_proc(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
-    let mut tokens = parsers::parser_line::cmd_to_tokens(line);
+    let tokens = parsers::parser_line::cmd_to_tokens(line);
+    run_tokens(sh, tokens, tty)
+}
+
+fn run_tokens(sh: &mut shell::Shell, tokens: types::Tokens, tty: bool) -> i32 {
+    let cmds = tokens_to_cmd_tokens(&tokens);
+    if cmds.len() > 1 && cmds.iter().any(|x| runs_in_shell(sh, x)) {
+        return run_subshell_pipeline(sh, &cmds);
+    }
+
+    let mut tokens = tokens;
     let envs = drain_env_tokens(&mut tokens);
 
     if tokens.is_empty() {
         for (name, value) in envs.iter() {
-            sh.set_env(name, value);
+            if let Err(e) = sh.set_env(name, value) {
+                println_stderr!("cicada: {}", e);
+                return 1;
+            }
         }
         return 0;
     }
-    let cmd = tokens[0].1.clone();
-
-    // for built-ins
-    if cmd == "cd" {
-        return builtins::cd::run(sh, &tokens);
-    }
-    if cmd == "export" {
-        return builtins::export::run(sh, line);
-    }
-    if cmd == "vox" {
-        return builtins::vox::run(sh, &tokens);
-    }
-    if cmd == "history" {
-        return builtins::history::run(&tokens);
-    }
-    if cmd == "exec" {
-        return builtins::exec::run(&tokens);
-    }
-    if cmd == "cinfo" {
-        return builtins::cinfo::run(&tokens);
+    for name in envs.keys() {
+        if sh.is_readonly(name) {
+            println_stderr!("cicada: {}: readonly variable", name);
+            return 1;
+        }
     }
-    if cmd == "exit" {
-        return builtins::exit::run(&tokens);
+    if runs_in_shell(sh, &tokens) {
+        return run_in_shell(sh, tokens, tty);
     }
 
     // for any other situations
@@ -208,7 +244,7 @@ pub fn run_proc(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
                 redirect_from = tokens.remove(idx).1;
                 len -= 1;
             } else {
-                println!("cicada: invalid command: cannot get redirect from");
+                println_stderr!("cicada: invalid command: cannot get redirect from");
                 return 1;
             }
         }
@@ -218,7 +254,7 @@ pub fn run_proc(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
     }
 
     let log_cmd = !sh.cmd.starts_with(" ");
-    let (result, term_given, _) = run_pipeline(
+    let (result, term_given, _, pid) = run_pipeline(
         tokens.clone(),
         redirect_from.as_str(),
         background,
@@ -227,6 +263,12 @@ pub fn run_proc(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
         log_cmd,
         Some(envs),
     );
+    if background {
+        sh.last_bg_pid = pid;
+        if pid > 0 {
+            sh.bg_pids.push(pid);
+        }
+    }
 
     if term_given {
         unsafe {
@@ -237,6 +279,240 @@ pub fn run_proc(sh: &mut shell::Shell, line: &str, tty: bool) -> i32 {
     result
 }
 
+/// Whether the command is a built-in or a function, which run in the
+/// shell process instead of a child one.
+fn runs_in_shell(sh: &shell::Shell, tokens: &types::Tokens) -> bool {
+    let mut tokens = tokens.clone();
+    drain_env_tokens(&mut tokens);
+    match tokens.first() {
+        Some(token) => builtins::is_builtin(&token.1) || sh.funcs.contains_key(&token.1),
+        None => false,
+    }
+}
+
+/// Run a built-in or a function, e.g. `export -p > vars.sh`; its
+/// redirections are applied to the shell's own stdio while it runs.
+fn run_in_shell(sh: &mut shell::Shell, tokens: types::Tokens, tty: bool) -> i32 {
+    let (redirect_from, cmd) = match split_redirects(tokens) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: {}", e);
+            return 1;
+        }
+    };
+    if cmd.tokens.is_empty() {
+        return 0;
+    }
+    if redirect_from.is_empty() && cmd.redirects.is_empty() {
+        return run_builtin_or_func(sh, &cmd.tokens, tty);
+    }
+
+    let saved = match redirect_stdio(&redirect_from, &cmd.redirects) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: {}", e);
+            return 1;
+        }
+    };
+    let status = run_builtin_or_func(sh, &cmd.tokens, tty);
+    restore_stdio(saved);
+    status
+}
+
+/// Take the `< file` and other redirections out of the tokens.
+fn split_redirects(tokens: types::Tokens) -> Result<(String, types::Command), String> {
+    let mut tokens = tokens;
+    let mut redirect_from = String::new();
+    if let Some(idx) = tokens.iter().position(|x| x.0.is_empty() && x.1 == "<") {
+        if idx + 1 >= tokens.len() {
+            return Err("invalid command: cannot get redirect from".to_string());
+        }
+        redirect_from = tokens.remove(idx + 1).1;
+        tokens.remove(idx);
+    }
+    let cmd = parsers::parser_line::cmd_to_with_redirects(&tokens)?;
+    Ok((redirect_from, cmd))
+}
+
+/// Run a compound command like `for ... done > out.txt` with `run`, while
+/// the redirections after it are applied to the shell's own stdio.
+pub fn run_redirected<F>(sh: &mut shell::Shell, redirects: &str, run: F) -> i32
+where
+    F: FnOnce(&mut shell::Shell) -> i32,
+{
+    let mut line = redirects.to_string();
+    tools::pre_handle_cmd_line(sh, &mut line);
+    let tokens = parsers::parser_line::cmd_to_tokens(&line);
+    let (redirect_from, cmd) = match split_redirects(tokens) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: {}", e);
+            return 1;
+        }
+    };
+    if let Some(token) = cmd.tokens.first() {
+        println_stderr!("cicada: syntax error near unexpected token `{}'", token.1);
+        return 2;
+    }
+    let saved = match redirect_stdio(&redirect_from, &cmd.redirects) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: {}", e);
+            return 1;
+        }
+    };
+    let status = run(sh);
+    restore_stdio(saved);
+    status
+}
+
+fn run_builtin_or_func(sh: &mut shell::Shell, tokens: &types::Tokens, tty: bool) -> i32 {
+    let cmd = tokens[0].1.clone();
+    if let Some(run) = builtins::find(&cmd) {
+        return run(sh, tokens, tty);
+    }
+    // user defined functions are found before programs in PATH
+    let args = tokens[1..].iter().map(|x| x.1.clone()).collect();
+    scripting::run_func(sh, &cmd, args, tty)
+}
+
+/// Point stdin, stdout and stderr of the shell to the redirections,
+/// returning copies of the original ones for `restore_stdio()`.
+fn redirect_stdio(redirect_from: &str, redirects: &[types::Redirection]) -> Result<[i32; 3], String> {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    let saved = unsafe { [libc::dup(0), libc::dup(1), libc::dup(2)] };
+
+    let mut result = Ok(());
+    if !redirect_from.is_empty() {
+        match File::open(redirect_from) {
+            Ok(file) => unsafe {
+                libc::dup2(file.as_raw_fd(), 0);
+            },
+            Err(e) => result = Err(format!("{}: {}", redirect_from, e)),
+        }
+    }
+    for (from_, op_, to_) in redirects {
+        if result.is_err() {
+            break;
+        }
+        let fd = if from_ == "2" { 2 } else { 1 };
+        if to_ == "&1" || to_ == "&2" {
+            let target = if to_ == "&1" { 1 } else { 2 };
+            unsafe {
+                libc::dup2(target, fd);
+            }
+            continue;
+        }
+        match tools::create_raw_fd_from_file(to_, op_ == ">>") {
+            Ok(target) => unsafe {
+                libc::dup2(target, fd);
+                libc::close(target);
+            },
+            Err(e) => result = Err(e),
+        }
+    }
+
+    match result {
+        Ok(_) => Ok(saved),
+        Err(e) => {
+            restore_stdio(saved);
+            Err(e)
+        }
+    }
+}
+
+fn restore_stdio(saved: [i32; 3]) {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    for (fd, old) in saved.iter().enumerate() {
+        unsafe {
+            libc::dup2(*old, fd as i32);
+            libc::close(*old);
+        }
+    }
+}
+
+/// Run a pipeline with built-ins or functions in it, e.g. `f | wc -l`.
+fn run_subshell_pipeline(sh: &mut shell::Shell, cmds: &[types::Tokens]) -> i32 {
+    run_forked_pipeline(sh, cmds.len(), |sh, i| run_tokens(sh, cmds[i].clone(), false))
+}
+
+/// Run a pipeline of `length` commands, where `run` runs the i-th one.
+/// Each of them runs in a forked copy of the shell, so that they can
+/// all read and write the pipes at the same time.
+pub fn run_forked_pipeline<F>(sh: &mut shell::Shell, length: usize, run: F) -> i32
+where
+    F: Fn(&mut shell::Shell, usize) -> i32,
+{
+    let mut pipes = Vec::new();
+    for _ in 0..length - 1 {
+        match pipe() {
+            Ok(x) => pipes.push(x),
+            Err(e) => {
+                println_stderr!("cicada: pipe error: {:?}", e);
+                close_pipes(&pipes);
+                return 1;
+            }
+        }
+    }
+
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    let mut pids = Vec::new();
+    let mut status = 0;
+    for i in 0..length {
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            println_stderr!("cicada: fork failed: {}", Error::last_os_error());
+            status = 1;
+            break;
+        }
+        if pid == 0 {
+            unsafe {
+                if i > 0 {
+                    libc::dup2(pipes[i - 1].0, 0);
+                }
+                if i < length - 1 {
+                    libc::dup2(pipes[i].1, 1);
+                }
+            }
+            close_pipes(&pipes);
+            let status = run(sh, i);
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            unsafe { libc::_exit(status) }
+        }
+        pids.push(pid);
+    }
+    close_pipes(&pipes);
+
+    for pid in pids {
+        let mut stat: i32 = 0;
+        // SIGCHLD of other children may interrupt the wait
+        while unsafe { libc::waitpid(pid, &mut stat, 0) } < 0 {
+            if Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                break;
+            }
+        }
+        if libc::WIFEXITED(stat) {
+            status = libc::WEXITSTATUS(stat);
+        } else if libc::WIFSIGNALED(stat) {
+            status = 128 + libc::WTERMSIG(stat);
+        }
+    }
+    status
+}
+
+fn close_pipes(pipes: &[(i32, i32)]) {
+    for fds in pipes {
+        unsafe {
+            libc::close(fds.0);
+            libc::close(fds.1);
+        }
+    }
+}
+
 fn run_calc_float(line: &str) -> Result<f64, String> {
     match parsers::parser_float::expr_float(line.as_bytes()) {
         IResult::Done(_, x) => Ok(x),
@@ -262,16 +538,18 @@ pub fn run_pipeline(
     capture_output: bool,
     log_cmd: bool,
     envs: Option<HashMap<String, String>>,
-) -> (i32, bool, Option<Output>) {
+) -> (i32, bool, Option<Output>, u32) {
     if background && capture_output {
         println_stderr!("cicada: cannot capture output of background cmd");
-        return (1, false, None);
+        return (1, false, None, 0);
     }
 
     // the defaults to return
     let mut status = 0;
     let mut term_given = false;
     let mut output = None;
+    // pid of the last process in pipeline, i.e. `$!` for background ones
+    let mut last_pid: u32 = 0;
 
     let sig_action = signal::SigAction::new(
         signal::SigHandler::Handler(handle_sigchld),
@@ -309,8 +587,8 @@ pub fn run_pipeline(
     }
 
     if length == 0 {
-        println!("cicada: invalid command: cmds with empty length");
-        return (1, false, None);
+        println_stderr!("cicada: invalid command: cmds with empty length");
+        return (1, false, None, 0);
     }
     let mut pipes = Vec::new();
     for _ in 0..length - 1 {
@@ -318,15 +596,15 @@ pub fn run_pipeline(
         match pipe() {
             Ok(x) => fds = x,
             Err(e) => {
-                println!("pipe error: {:?}", e);
-                return (1, false, None);
+                println_stderr!("cicada: pipe error: {:?}", e);
+                return (1, false, None, 0);
             }
         }
         pipes.push(fds);
     }
     if pipes.len() + 1 != length {
-        println!("cicada: invalid command: unmatched pipes count");
-        return (1, false, None);
+        println_stderr!("cicada: invalid command: unmatched pipes count");
+        return (1, false, None, 0);
     }
 
     let isatty = if tty {
@@ -350,16 +628,16 @@ pub fn run_pipeline(
                 cmd_new = x;
             }
             Err(e) => {
-                println!("cicada: cmd_to_with_redirects failed: {:?}", e);
-                return (1, false, None);
+                println_stderr!("cicada: {}", e);
+                return (1, false, None, 0);
             }
         }
 
         let cmd_ = parsers::parser_line::tokens_to_args(&cmd_new.tokens);
 
         if cmd_.is_empty() {
-            println!("cicada: cmd_ is empty");
-            return (1, false, None);
+            println_stderr!("cicada: cmd_ is empty");
+            return (1, false, None, 0);
         }
         let program = &cmd_[0];
         // treat `(ls)` as `ls`
@@ -409,8 +687,8 @@ pub fn run_pipeline(
                 unsafe {
                     if i < length - 1 {
                         let fds = pipes[i];
-                        let pipe_out = Stdio::from_raw_fd(fds.1);
-                        p.stderr(pipe_out);
+                        let fd = libc::dup(fds.1);
+                        p.stderr(Stdio::from_raw_fd(fd));
                     } else if !capture_output {
                         let fd = libc::dup(1);
                         p.stderr(Stdio::from_raw_fd(fd));
@@ -441,7 +719,7 @@ pub fn run_pipeline(
                     }
                     Err(e) => {
                         println_stderr!("cicada: {}", e);
-                        return (1, false, None);
+                        return (1, false, None, 0);
                     }
                 }
             }
@@ -465,11 +743,18 @@ pub fn run_pipeline(
                 child = x;
                 if i != length - 1 {
                     children.push(child.id());
+                } else {
+                    last_pid = child.id();
                 }
             }
             Err(e) => {
-                println!("{}: {}", program, e.description());
-                status = 1;
+                if e.kind() == io::ErrorKind::NotFound {
+                    println_stderr!("cicada: {}: command not found", program);
+                    status = 127;
+                } else {
+                    println_stderr!("cicada: {}: {}", program, e);
+                    status = 126;
+                }
                 continue;
             }
         }
@@ -497,6 +782,12 @@ pub fn run_pipeline(
                     Ok(ecode) => {
                         if ecode.success() {
                             status = 0;
+                        } else if let Some(sig) = ecode.signal() {
+                            // killed by a signal, e.g. 130 for Ctrl-C
+                            status = 128 + sig;
+                            if sig == libc::SIGINT {
+                                shell::INTERRUPTED.store(true, Ordering::SeqCst);
+                            }
                         } else {
                             match ecode.code() {
                                 Some(x) => status = x,
@@ -534,7 +825,7 @@ pub fn run_pipeline(
         }
         i += 1;
     }
-    (status, term_given, output)
+    (status, term_given, output, last_pid)
 }
 
 fn run_with_shell<'a, 'b>(sh: &'a mut shell::Shell, line: &'b str) -> Result<CommandResult, &'b str> {
@@ -544,7 +835,9 @@ fn run_with_shell<'a, 'b>(sh: &'a mut shell::Shell, line: &'b str) -> Result<Com
     let envs = drain_env_tokens(&mut tokens);
     if tokens.is_empty() {
         for (name, value) in envs.iter() {
-            sh.set_env(name, value);
+            if sh.set_env(name, value).is_err() {
+                return Err("cicada: readonly variable");
+            }
         }
         return Ok(CommandResult::new());
     }
@@ -572,7 +865,7 @@ fn run_with_shell<'a, 'b>(sh: &'a mut shell::Shell, line: &'b str) -> Result<Com
         return Ok(CommandResult::new());
     }
 
-    let (status, _, output) = run_pipeline(
+    let (status, _, output, _) = run_pipeline(
         tokens.clone(),
         redirect_from.as_str(),
         false,
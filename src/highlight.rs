@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+use regex::Regex;
+
+use builtins;
+use completers::path;
+use libs;
+use parsers;
+use shell;
+use tools;
+
+/// Shell keywords; after the ones in the first list a command is expected.
+const KEYWORDS_BEFORE_CMD: &[&str] = &[
+    "if", "then", "else", "elif", "do", "while", "until", "!", "{",
+];
+const KEYWORDS: &[&str] = &["fi", "for", "in", "done", "case", "esac", "function", "}"];
+
+thread_local! {
+    /// Whether the command names typed so far are in `$PATH`, so that
+    /// it's searched once per name and prompt instead of on every key.
+    static IN_PATH: RefCell<HashMap<String, bool>> = RefCell::new(HashMap::new());
+}
+
+/// Forget the commands found in `$PATH`, before each prompt, as it or
+/// the programs in it may have changed since the last one.
+pub fn clear_path_cache() {
+    IN_PATH.with(|x| x.borrow_mut().clear());
+}
+
+fn is_in_path(name: &str) -> bool {
+    IN_PATH.with(|x| {
+        let mut cache = x.borrow_mut();
+        if let Some(found) = cache.get(name) {
+            return *found;
+        }
+        let found = path::is_in_path(name);
+        cache.insert(name.to_string(), found);
+        found
+    })
+}
+
+/// The colors of a kind of token, which can be changed with variables
+/// like `HIGHLIGHT_COMMAND=bold green`.
+fn color(sh: &shell::Shell, kind: &str) -> String {
+    let names = match sh.get_env(&format!("HIGHLIGHT_{}", kind)) {
+        Some(x) => x,
+        None => match kind {
+            "COMMAND" => "green",
+            "ERROR" => "red",
+            "STRING" => "yellow",
+            "VARIABLE" => "cyan",
+            "OPERATOR" => "magenta",
+            "REDIRECT" => "magenta",
+            "PATH" => "underline",
+            "SUGGESTION" => "grey",
+            _ => "",
+        }
+        .to_string(),
+    };
+    libs::colored::raw_seq(&names)
+}
+
+pub fn paint(sh: &shell::Shell, kind: &str, text: &str) -> String {
+    let seq = color(sh, kind);
+    if seq.is_empty() {
+        return text.to_string();
+    }
+    format!("{}{}\x1B[0m", seq, text)
+}
+
+fn is_valid_cmd(sh: &shell::Shell, name: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+    if name.contains('/') {
+        let mut name = name.to_string();
+        tools::extend_home(&mut name);
+        return Path::new(&name).exists();
+    }
+    sh.alias.contains_key(name)
+        || sh.funcs.contains_key(name)
+        || builtins::is_builtin(name)
+        || name == "[["
+        || is_in_path(name)
+}
+
+/// Length in bytes of the first char of `s`, 0 if it's empty.
+fn char_len(s: &str) -> usize {
+    s.chars().next().map_or(0, |c| c.len_utf8())
+}
+
+/// Where the string quoted by the char at `start` of `word` ends, i.e.
+/// after its closing quote; in double quotes a backslash escapes it.
+fn quote_end(word: &str, start: usize) -> usize {
+    let quote = word.as_bytes()[start];
+    let mut escaped = false;
+    for (i, c) in word[start + 1..].bytes().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if c == b'\\' && quote == b'"' {
+            escaped = true;
+        } else if c == quote {
+            return start + i + 2;
+        }
+    }
+    word.len()
+}
+
+/// Color the quoted strings and variables in a word.
+fn paint_word(sh: &shell::Shell, word: &str) -> String {
+    let mut result = String::new();
+    let bytes = word.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == b'\'' || c == b'"' {
+            let end = quote_end(word, i);
+            result.push_str(&paint(sh, "STRING", &word[i..end]));
+            i = end;
+        } else if c == b'$' && i + 1 < bytes.len() {
+            let end = if bytes[i + 1] == b'{' {
+                word[i..].find('}').map_or(word.len(), |x| i + x + 1)
+            } else {
+                let name_len = word[i + 1..]
+                    .bytes()
+                    .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_')
+                    .count();
+                i + 1 + name_len.max(char_len(&word[i + 1..]))
+            };
+            result.push_str(&paint(sh, "VARIABLE", &word[i..end]));
+            i = end;
+        } else {
+            let mut end = i + char_len(&word[i..]);
+            if c == b'\\' {
+                end += char_len(&word[end..]);
+            }
+            result.push_str(&word[i..end]);
+            i = end;
+        }
+    }
+    result
+}
+
+/// Color a redirection like `>out`, `2>&1` or `2>` the way
+/// `cmd_to_with_redirects()` finds them in a word; None if it's not one.
+fn paint_redirect(sh: &shell::Shell, word: &str) -> Option<String> {
+    let re = Regex::new(r"^([^>]*)(>>?)([^>]*)$").ok()?;
+    let caps = re.captures(word)?;
+    let fd = caps.get(1).map_or("", |x| x.as_str());
+    let op = caps.get(2).map_or("", |x| x.as_str());
+    let target = caps.get(3).map_or("", |x| x.as_str());
+
+    let mut result = String::new();
+    let mut redirect = op.to_string();
+    if tools::re_contains(fd, r"^\d+$") {
+        redirect = format!("{}{}", fd, op);
+    } else {
+        result.push_str(&paint_word(sh, fd));
+    }
+    if target.starts_with('&') {
+        redirect.push_str(target);
+        result.push_str(&paint(sh, "REDIRECT", &redirect));
+    } else {
+        result.push_str(&paint(sh, "REDIRECT", &redirect));
+        result.push_str(&paint_word(sh, target));
+    }
+    Some(result)
+}
+
+/// The input line with escape sequences of colors added: commands in
+/// green if they can be found, otherwise in red, and strings, variables,
+/// operators, redirections and existing paths highlighted too. It's
+/// split into commands and words by the parsers that run it, thus it
+/// keeps their quotes and backslashes.
+pub fn highlight(sh: &shell::Shell, line: &str) -> String {
+    let mut result = String::new();
+    for range in parsers::parser_script::piece_ranges(line) {
+        let piece = &line[range];
+        if piece == ";" || piece == ";;" {
+            result.push_str(&paint(sh, "OPERATOR", piece));
+        } else {
+            result.push_str(&highlight_cmd_list(sh, piece));
+        }
+    }
+    result
+}
+
+/// Highlight commands joined with `&&` and `||`.
+fn highlight_cmd_list(sh: &shell::Shell, line: &str) -> String {
+    let mut result = String::new();
+    let mut pos = 0;
+    for range in parsers::parser_line::line_to_cmd_ranges(line) {
+        result.push_str(&line[pos..range.start]);
+        let text = &line[range.clone()];
+        if text == ";" || text == "&&" || text == "||" {
+            result.push_str(&paint(sh, "OPERATOR", text));
+        } else {
+            result.push_str(&highlight_cmd(sh, text));
+        }
+        pos = range.end;
+    }
+    // e.g. a comment
+    result.push_str(&line[pos..]);
+    result
+}
+
+/// Highlight one command of the line, i.e. words and pipes.
+fn highlight_cmd(sh: &shell::Shell, cmd: &str) -> String {
+    let mut result = String::new();
+    let mut expect_cmd = true;
+    let mut pos = 0;
+    for ((sep, value), range) in parsers::parser_line::cmd_to_token_ranges(cmd) {
+        result.push_str(&cmd[pos..range.start]);
+        let word = &cmd[range.clone()];
+        pos = range.end;
+
+        if sep.is_empty() {
+            if value == "|" || value == "||" || value == "&" {
+                result.push_str(&paint(sh, "OPERATOR", word));
+                expect_cmd = true;
+                continue;
+            }
+            if value == "<" {
+                result.push_str(&paint(sh, "REDIRECT", word));
+                continue;
+            }
+            // unquoted words only lose their backslashes when read,
+            // so the `>` is where it is in the value
+            if word.contains('>') {
+                if let Some(x) = paint_redirect(sh, word) {
+                    result.push_str(&x);
+                    continue;
+                }
+            }
+        }
+
+        if expect_cmd {
+            if tools::re_contains(word, r"^[a-zA-Z_][a-zA-Z0-9_]*=") {
+                result.push_str(&paint_word(sh, word));
+            } else if KEYWORDS_BEFORE_CMD.contains(&word) {
+                result.push_str(&paint(sh, "COMMAND", word));
+            } else if KEYWORDS.contains(&word) {
+                result.push_str(&paint(sh, "COMMAND", word));
+                expect_cmd = false;
+            } else if word.contains('$') || word.contains('`') {
+                result.push_str(&paint_word(sh, word));
+                expect_cmd = false;
+            } else {
+                let kind = if is_valid_cmd(sh, &value) {
+                    "COMMAND"
+                } else {
+                    "ERROR"
+                };
+                result.push_str(&paint(sh, kind, word));
+                expect_cmd = false;
+            }
+            continue;
+        }
+
+        let is_plain = !word.contains(['\'', '"', '$']);
+        let mut path = value.clone();
+        tools::extend_home(&mut path);
+        if is_plain && !value.is_empty() && Path::new(&path).exists() {
+            result.push_str(&paint(sh, "PATH", word));
+        } else {
+            result.push_str(&paint_word(sh, word));
+        }
+    }
+    result.push_str(&cmd[pos..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::highlight;
+    use shell::Shell;
+
+    #[test]
+    fn test_highlight() {
+        let mut sh = Shell::new();
+        sh.alias.insert("ll".to_string(), "ls -l".to_string());
+        let line = "ll /etc 'a b' $HOME>x 2>&1 && no-such-cmd-x | cd";
+        let expected = "\x1B[32mll\x1B[0m \x1B[4m/etc\x1B[0m \x1B[33m'a b'\x1B[0m \
+                        \x1B[36m$HOME\x1B[0m\x1B[35m>\x1B[0mx \x1B[35m2>&1\x1B[0m \
+                        \x1B[35m&&\x1B[0m \x1B[31mno-such-cmd-x\x1B[0m \x1B[35m|\x1B[0m \
+                        \x1B[32mcd\x1B[0m";
+        assert_eq!(highlight(&sh, line), expected);
+
+        sh.set_env("HIGHLIGHT_COMMAND", "bold blue").unwrap();
+        sh.set_env("HIGHLIGHT_VARIABLE", "").unwrap();
+        let expected = "\x1B[1m\x1B[34mif\x1B[0m X=$a \x1B[1m\x1B[34mtest\x1B[0m";
+        assert_eq!(highlight(&sh, "if X=$a test"), expected);
+        let expected = "\x1B[1m\x1B[34mecho\x1B[0m 中\\文 $中";
+        assert_eq!(highlight(&sh, "echo 中\\文 $中"), expected);
+    }
+
+    #[test]
+    fn test_highlight_quoted() {
+        let sh = Shell::new();
+        // quoted `|` and `;` are not operators
+        let line = "echo 'a | b' \"c;d\" x\\|y";
+        let expected = "\x1B[32mecho\x1B[0m \x1B[33m'a | b'\x1B[0m \x1B[33m\"c;d\"\x1B[0m x\\|y";
+        assert_eq!(highlight(&sh, line), expected);
+        let line = "echo \"a\\\"|\" | cat; ls";
+        let expected = "\x1B[32mecho\x1B[0m \x1B[33m\"a\\\"|\"\x1B[0m \x1B[35m|\x1B[0m \
+                        \x1B[32mcat\x1B[0m\x1B[35m;\x1B[0m \x1B[32mls\x1B[0m";
+        assert_eq!(highlight(&sh, line), expected);
+
+        // `\ ` is part of the word, also of the command
+        let line = "no\\ such-cmd a\\ b>x";
+        let expected = "\x1B[31mno\\ such-cmd\x1B[0m a\\ b\x1B[35m>\x1B[0mx";
+        assert_eq!(highlight(&sh, line), expected);
+    }
+}
@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
@@ -7,7 +8,9 @@ use std::path::Path;
 use linefeed::terminal::DefaultTerminal;
 use linefeed::Interface;
 use sqlite;
+use sqlite::State;
 
+use parsers;
 use shell;
 use tools;
 
@@ -76,6 +79,7 @@ pub fn init(rl: &mut Interface<DefaultTerminal>) {
                 Ok(_) => {}
                 Err(e) => println_stderr!("cicada: sqlite exec error - {:?}", e),
             }
+            upgrade_table(&conn, &history_table);
 
             if let Ok(x) = env::var("HISTORY_DELETE_DUPS") {
                 if x == "1" {
@@ -113,6 +117,31 @@ pub fn init(rl: &mut Interface<DefaultTerminal>) {
     }
 }
 
+/// Add the `cwd` column to tables created by older versions, and the
+/// index for prefix searches of autosuggestions.
+fn upgrade_table(conn: &sqlite::Connection, history_table: &str) {
+    let mut has_cwd = false;
+    let sql = format!("PRAGMA table_info({});", history_table);
+    let _ = conn.iterate(sql, |pairs| {
+        for &(column, value) in pairs.iter() {
+            if column == "name" && value == Some("cwd") {
+                has_cwd = true;
+            }
+        }
+        true
+    });
+    let mut sql = format!(
+        "CREATE INDEX IF NOT EXISTS {}_inp ON {} (inp);",
+        history_table, history_table
+    );
+    if !has_cwd {
+        sql = format!("ALTER TABLE {} ADD COLUMN cwd TEXT; {}", history_table, sql);
+    }
+    if let Err(e) = conn.execute(sql) {
+        println_stderr!("cicada: sqlite exec error - {:?}", e);
+    }
+}
+
 pub fn get_history_file() -> String {
     if let Ok(hfile) = env::var("HISTORY_FILE") {
         return hfile;
@@ -155,6 +184,7 @@ pub fn add(
     sh: &mut shell::Shell,
     rl: &mut Interface<DefaultTerminal>,
     line: &str,
+    cwd: &str,
     status: i32,
     tsb: f64,
     tse: f64,
@@ -182,17 +212,532 @@ pub fn add(
     }
     let sql = format!(
         "INSERT INTO \
-         {} (inp, rtn, tsb, tse, sessionid) \
-         VALUES('{}', {}, {}, {}, '{}');",
+         {} (inp, rtn, tsb, tse, sessionid, cwd) \
+         VALUES('{}', {}, {}, {}, '{}', '{}');",
         history_table,
         str::replace(line.trim(), "'", "''"),
         status,
         tsb,
         tse,
-        "cicada"
+        "cicada",
+        str::replace(cwd, "'", "''"),
     );
     match conn.execute(sql) {
         Ok(_) => {}
         Err(e) => println!("failed to save history: {:?}", e),
     }
 }
+
+thread_local! {
+    /// The history file and the connection to it that suggestions are
+    /// looked up with, kept for the session instead of opened per key.
+    static SUGGEST_CONN: RefCell<Option<(String, sqlite::Connection)>> = const { RefCell::new(None) };
+}
+
+/// The rest of the most recent successful command starting with
+/// `prefix`, preferring the ones run in `cwd`, for autosuggestions.
+pub fn suggest(prefix: &str, cwd: &str) -> Option<String> {
+    let hfile = get_history_file();
+    SUGGEST_CONN.with(|x| {
+        let mut cached = x.borrow_mut();
+        let is_open = match *cached {
+            Some((ref file, _)) => *file == hfile,
+            None => false,
+        };
+        if !is_open {
+            *cached = None;
+            let conn = sqlite::open(&hfile).ok()?;
+            *cached = Some((hfile, conn));
+        }
+        let (_, ref conn) = *cached.as_ref()?;
+        find_suggestion(conn, &get_history_table(), prefix, cwd)
+    })
+}
+
+fn find_suggestion(
+    conn: &sqlite::Connection,
+    history_table: &str,
+    prefix: &str,
+    cwd: &str,
+) -> Option<String> {
+    if prefix.is_empty() {
+        return None;
+    }
+    // a range instead of LIKE, so that the index of inp is used
+    let sql = format!(
+        "SELECT inp FROM {} WHERE inp > ? AND inp < ? AND rtn = 0 \
+         ORDER BY cwd = ? DESC, tsb DESC LIMIT 1;",
+        history_table
+    );
+    let mut statement = conn.prepare(sql).ok()?;
+    let upper = format!("{}\u{10FFFF}", prefix);
+    statement.bind(1, prefix).ok()?;
+    statement.bind(2, upper.as_str()).ok()?;
+    statement.bind(3, cwd).ok()?;
+    match statement.next() {
+        Ok(State::Row) => {
+            let inp = statement.read::<String>(0).ok()?;
+            Some(inp[prefix.len()..].to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Event designators of history expansion, e.g. `!!`, `!42`, `!-2`,
+/// `!git` and `!?text?`.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Last,
+    Number(i64),
+    Relative(usize),
+    Prefix(String),
+    Contains(String),
+}
+
+/// Find the command line of an event from the history database.
+pub fn search_event(event: &Event) -> Option<String> {
+    let history_table = get_history_table();
+    let conn = match sqlite::open(get_history_file()) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: sqlite open db error: {:?}", e);
+            return None;
+        }
+    };
+    let sql = match *event {
+        Event::Number(_) => format!("SELECT inp FROM {} WHERE rowid = ?;", history_table),
+        Event::Last | Event::Relative(_) => format!(
+            "SELECT inp FROM {} ORDER BY tsb DESC LIMIT 1 OFFSET ?;",
+            history_table
+        ),
+        Event::Prefix(_) => format!(
+            "SELECT inp FROM {} WHERE instr(inp, ?) = 1 ORDER BY tsb DESC LIMIT 1;",
+            history_table
+        ),
+        Event::Contains(_) => format!(
+            "SELECT inp FROM {} WHERE instr(inp, ?) > 0 ORDER BY tsb DESC LIMIT 1;",
+            history_table
+        ),
+    };
+    let mut statement = match conn.prepare(sql) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("cicada: history prepare error - {:?}", e);
+            return None;
+        }
+    };
+    let bound = match *event {
+        Event::Last => statement.bind(1, 0),
+        Event::Number(n) => statement.bind(1, n),
+        Event::Relative(n) => statement.bind(1, n as i64 - 1),
+        Event::Prefix(ref s) | Event::Contains(ref s) => statement.bind(1, s.as_str()),
+    };
+    if let Err(e) = bound {
+        println_stderr!("cicada: history bind error - {:?}", e);
+        return None;
+    }
+    match statement.next() {
+        Ok(State::Row) => statement.read::<String>(0).ok(),
+        _ => None,
+    }
+}
+
+/// Performs csh style history expansion on the line, using `lookup` to
+/// find the command lines of events. Returns the new line and whether it
+/// should only be printed instead of being run (the `:p` modifier).
+pub fn expand<F>(line: &str, lookup: F) -> Result<(String, bool), String>
+where
+    F: Fn(&Event) -> Option<String>,
+{
+    if line.starts_with('^') {
+        return quick_substitute(line, &lookup).map(|x| (x, false));
+    }
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut result = String::new();
+    let mut print_only = false;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\\' && !in_single {
+            result.push(c);
+            if i + 1 < chars.len() {
+                result.push(chars[i + 1]);
+            }
+            i += 2;
+            continue;
+        }
+        if c == '\'' && !in_double {
+            in_single = !in_single;
+        } else if c == '"' && !in_single {
+            in_double = !in_double;
+        }
+        if c != '!' || in_single || (i > 0 && (chars[i - 1] == '$' || chars[i - 1] == '{')) {
+            result.push(c);
+            i += 1;
+            continue;
+        }
+
+        match expand_designator(&chars, i, &lookup)? {
+            Some((text, len, _print_only)) => {
+                result.push_str(&text);
+                print_only = print_only || _print_only;
+                i += len;
+            }
+            None => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+    Ok((result, print_only))
+}
+
+fn quick_substitute<F>(line: &str, lookup: &F) -> Result<String, String>
+where
+    F: Fn(&Event) -> Option<String>,
+{
+    let parts: Vec<&str> = line[1..].splitn(3, '^').collect();
+    if parts.len() < 2 || parts[0].is_empty() {
+        return Err(format!("{}: bad quick substitution", line));
+    }
+    let previous = match lookup(&Event::Last) {
+        Some(x) => x,
+        None => return Err(String::from("!!: event not found")),
+    };
+    if !previous.contains(parts[0]) {
+        return Err(format!("{}: substitution failed", line));
+    }
+    let mut result = previous.replacen(parts[0], parts[1], 1);
+    if parts.len() == 3 {
+        result.push_str(parts[2]);
+    }
+    Ok(result)
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || ";&|<>()'\"`".contains(c)
+}
+
+/// Expand the history designator starting at `chars[start]` (a `!`).
+/// Returns the expanded text, how many chars were consumed and whether
+/// the `:p` modifier was given, or `None` if the `!` is a literal one.
+fn expand_designator<F>(
+    chars: &[char],
+    start: usize,
+    lookup: &F,
+) -> Result<Option<(String, usize, bool)>, String>
+where
+    F: Fn(&Event) -> Option<String>,
+{
+    let len = chars.len();
+    let mut j = start + 1;
+    if j >= len || chars[j].is_whitespace() || chars[j] == '=' || chars[j] == '(' {
+        return Ok(None);
+    }
+
+    let event;
+    if chars[j] == '!' {
+        event = Event::Last;
+        j += 1;
+    } else if chars[j].is_ascii_digit() {
+        let digits = take_while(chars, &mut j, |c| c.is_ascii_digit());
+        event = Event::Number(digits.parse::<i64>().unwrap_or(0));
+    } else if chars[j] == '-' && j + 1 < len && chars[j + 1].is_ascii_digit() {
+        j += 1;
+        let digits = take_while(chars, &mut j, |c| c.is_ascii_digit());
+        event = Event::Relative(digits.parse::<usize>().unwrap_or(0));
+    } else if chars[j] == '?' {
+        j += 1;
+        let text = take_while(chars, &mut j, |c| c != '?' && c != '\n');
+        if j < len && chars[j] == '?' {
+            j += 1;
+        }
+        event = Event::Contains(text);
+    } else if "$^*:".contains(chars[j]) {
+        event = Event::Last;
+    } else {
+        let text = take_while(chars, &mut j, |c| !is_word_end(c) && !":$^*".contains(c));
+        if text.is_empty() {
+            return Ok(None);
+        }
+        event = Event::Prefix(text);
+    }
+
+    let designator: String = chars[start..j].iter().collect();
+    let cmd = match lookup(&event) {
+        Some(x) => x,
+        None => return Err(format!("{}: event not found", designator)),
+    };
+
+    // word designators
+    let mut words: Vec<String> = parsers::parser_line::cmd_to_tokens(&cmd)
+        .iter()
+        .map(|x| tools::wrap_sep_string(&x.0, &x.1))
+        .collect();
+    let has_colon = j + 1 < len && chars[j] == ':' && "0123456789^$*-".contains(chars[j + 1]);
+    if has_colon || (j < len && "^$*".contains(chars[j])) {
+        if has_colon {
+            j += 1;
+        }
+        let last = if words.is_empty() { 0 } else { words.len() - 1 };
+        let (from, to) = match chars[j] {
+            '^' => {
+                j += 1;
+                (1, 1)
+            }
+            '$' => {
+                j += 1;
+                (last, last)
+            }
+            '*' => {
+                j += 1;
+                (1, last)
+            }
+            '-' => {
+                j += 1;
+                let digits = take_while(chars, &mut j, |c| c.is_ascii_digit());
+                (0, digits.parse::<usize>().unwrap_or(last))
+            }
+            _ => {
+                let digits = take_while(chars, &mut j, |c| c.is_ascii_digit());
+                let from = digits.parse::<usize>().unwrap_or(0);
+                if j < len && chars[j] == '*' {
+                    j += 1;
+                    (from, last)
+                } else if j < len && chars[j] == '-' {
+                    j += 1;
+                    if j < len && chars[j] == '$' {
+                        j += 1;
+                        (from, last)
+                    } else if j < len && chars[j].is_ascii_digit() {
+                        let digits = take_while(chars, &mut j, |c| c.is_ascii_digit());
+                        (from, digits.parse::<usize>().unwrap_or(0))
+                    } else if last > 0 {
+                        (from, last - 1)
+                    } else {
+                        (from, 0)
+                    }
+                } else {
+                    (from, from)
+                }
+            }
+        };
+        if from > to {
+            // e.g. `!*` of a command without arguments
+            words = Vec::new();
+        } else if to >= words.len() {
+            let designator: String = chars[start..j].iter().collect();
+            return Err(format!("{}: bad word specifier", designator));
+        } else {
+            words = words[from..to + 1].to_vec();
+        }
+    }
+    let mut text = words.join(" ");
+
+    // modifiers
+    let mut print_only = false;
+    while j + 1 < len && chars[j] == ':' {
+        let m = chars[j + 1];
+        if m == 'h' || m == 't' || m == 'r' || m == 'e' {
+            text = apply_path_modifier(&text, m);
+            j += 2;
+        } else if m == 'p' {
+            print_only = true;
+            j += 2;
+        } else if m == 's' || (m == 'g' && j + 2 < len && chars[j + 2] == 's') {
+            let global = m == 'g';
+            j += if global { 3 } else { 2 };
+            if j >= len {
+                return Err(String::from(":s: bad substitution"));
+            }
+            let delimiter = chars[j];
+            j += 1;
+            let old = take_while(chars, &mut j, |c| c != delimiter);
+            if j < len {
+                j += 1;
+            }
+            let new = take_while(chars, &mut j, |c| c != delimiter && c != '\n');
+            if j < len && chars[j] == delimiter {
+                j += 1;
+            }
+            if old.is_empty() || !text.contains(old.as_str()) {
+                return Err(format!(":s{}{}{}{}: substitution failed", delimiter, old, delimiter, new));
+            }
+            text = if global {
+                text.replace(old.as_str(), &new)
+            } else {
+                text.replacen(old.as_str(), &new, 1)
+            };
+        } else {
+            break;
+        }
+    }
+
+    Ok(Some((text, j - start, print_only)))
+}
+
+fn take_while<P>(chars: &[char], j: &mut usize, predicate: P) -> String
+where
+    P: Fn(char) -> bool,
+{
+    let mut result = String::new();
+    while *j < chars.len() && predicate(chars[*j]) {
+        result.push(chars[*j]);
+        *j += 1;
+    }
+    result
+}
+
+fn apply_path_modifier(text: &str, modifier: char) -> String {
+    match modifier {
+        // remove the trailing pathname component
+        'h' => match text.rfind('/') {
+            Some(0) => String::from("/"),
+            Some(pos) => text[..pos].to_string(),
+            None => text.to_string(),
+        },
+        // remove all leading pathname components
+        't' => match text.rfind('/') {
+            Some(pos) => text[pos + 1..].to_string(),
+            None => text.to_string(),
+        },
+        // remove the trailing suffix
+        'r' => match text.rfind('.') {
+            Some(pos) if !text[pos..].contains('/') => text[..pos].to_string(),
+            _ => text.to_string(),
+        },
+        // keep only the trailing suffix
+        'e' => match text.rfind('.') {
+            Some(pos) if !text[pos..].contains('/') => text[pos + 1..].to_string(),
+            _ => String::new(),
+        },
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+    use super::find_suggestion;
+    use super::upgrade_table;
+    use super::Event;
+    use sqlite;
+
+    fn lookup(event: &Event) -> Option<String> {
+        let histories = [
+            "ls -lh /usr/local/lib/libfoo.so.1",
+            "git commit -m \"fix it\"",
+            "vim src/main.rs",
+            "echo foo bar baz",
+        ];
+        match *event {
+            Event::Last => Some(histories[3].to_string()),
+            Event::Number(n) => histories.get(n as usize - 1).map(|x| x.to_string()),
+            Event::Relative(n) => histories.get(histories.len() - n).map(|x| x.to_string()),
+            Event::Prefix(ref s) => histories
+                .iter()
+                .rev()
+                .find(|x| x.starts_with(s.as_str()))
+                .map(|x| x.to_string()),
+            Event::Contains(ref s) => histories
+                .iter()
+                .rev()
+                .find(|x| x.contains(s.as_str()))
+                .map(|x| x.to_string()),
+        }
+    }
+
+    fn assert_expand(line: &str, expected: &str) {
+        match expand(line, lookup) {
+            Ok((x, _)) => assert_eq!(x, expected),
+            Err(e) => panic!("expand {} failed: {}", line, e),
+        }
+    }
+
+    #[test]
+    fn test_expand_events() {
+        assert_expand("echo hi", "echo hi");
+        assert_expand("sudo !!", "sudo echo foo bar baz");
+        assert_expand("!3", "vim src/main.rs");
+        assert_expand("!-3", "git commit -m \"fix it\"");
+        assert_expand("!git", "git commit -m \"fix it\"");
+        assert_expand("!?lib?", "ls -lh /usr/local/lib/libfoo.so.1");
+        assert_expand("!?main", "vim src/main.rs");
+        assert_expand("!vim; ls", "vim src/main.rs; ls");
+        assert!(expand("!nothing", lookup).is_err());
+    }
+
+    #[test]
+    fn test_expand_literals() {
+        assert_expand("echo '!!'", "echo '!!'");
+        assert_expand("echo \\!!", "echo \\!!");
+        assert_expand("echo $! ${!}", "echo $! ${!}");
+        assert_expand("test a != b", "test a != b");
+        assert_expand("! true", "! true");
+        assert_expand("echo hi!", "echo hi!");
+    }
+
+    #[test]
+    fn test_expand_words() {
+        assert_expand("ls !$", "ls baz");
+        assert_expand("ls !^", "ls foo");
+        assert_expand("ls !*", "ls foo bar baz");
+        assert_expand("ls !!:2", "ls bar");
+        assert_expand("ls !!:1-2", "ls foo bar");
+        assert_expand("ls !!:2*", "ls bar baz");
+        assert_expand("ls !!:2-", "ls bar");
+        assert_expand("!!:0 hi", "echo hi");
+        assert_expand("echo !git:3", "echo \"fix it\"");
+        assert_expand("echo !vim$", "echo src/main.rs");
+        assert!(expand("ls !!:9", lookup).is_err());
+    }
+
+    #[test]
+    fn test_expand_modifiers() {
+        assert_expand("cd !ls:$:h", "cd /usr/local/lib");
+        assert_expand("echo !ls:$:t", "echo libfoo.so.1");
+        assert_expand("echo !vim:$:r", "echo src/main");
+        assert_expand("echo !vim:$:e", "echo rs");
+        assert_expand("!!:s/foo/xx/", "echo xx bar baz");
+        assert_expand("!!:gs/ba/BA/", "echo foo BAr BAz");
+        assert!(expand("!!:s/nope/xx/", lookup).is_err());
+
+        match expand("!vim:p", lookup) {
+            Ok((x, print_only)) => {
+                assert_eq!(x, "vim src/main.rs");
+                assert!(print_only);
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_quick_substitution() {
+        assert_expand("^foo^qux", "echo qux bar baz");
+        assert_expand("^foo^qux^", "echo qux bar baz");
+        assert_expand("^bar^^", "echo foo  baz");
+        assert!(expand("^nope^qux", lookup).is_err());
+    }
+
+    #[test]
+    fn test_find_suggestion() {
+        let conn = sqlite::open(":memory:").unwrap();
+        let sql = "CREATE TABLE h (inp TEXT, rtn INTEGER, tsb REAL, tse REAL, \
+                   sessionid TEXT, out TEXT, info TEXT);";
+        conn.execute(sql).unwrap();
+        upgrade_table(&conn, "h");
+        let sql = "INSERT INTO h (inp, rtn, tsb, cwd) VALUES \
+                   ('git status', 0, 1, '/a'), ('git stash', 0, 2, '/b'), \
+                   ('git show', 1, 3, '/a'), ('git', 0, 4, '/a'), ('ls', 0, 5, '/a');";
+        conn.execute(sql).unwrap();
+        let suggest = |prefix, cwd| find_suggestion(&conn, "h", prefix, cwd);
+        assert_eq!(suggest("git s", "/a"), Some("tatus".to_string()));
+        assert_eq!(suggest("git s", "/c"), Some("tash".to_string()));
+        assert_eq!(suggest("gi", "/a"), Some("t".to_string()));
+        assert_eq!(suggest("ls", "/a"), None);
+        assert_eq!(suggest("", "/a"), None);
+    }
+}
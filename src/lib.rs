@@ -23,6 +23,7 @@ mod execute;
 mod history;
 mod libs;
 mod parsers;
+mod scripting;
 mod shell;
 
 use tools::CommandResult;
@@ -1,16 +1,50 @@
 // setting prompt in crate linefeed needs wrap every SEQ chars
 // with prefixing with '\x01' and suffix with '\x02'.
-const BLUE: &str = "\x01\x1B[34m\x02";
+const BLACK: &str = "\x01\x1B[30m\x02";
 const RED: &str = "\x01\x1B[31m\x02";
 const GREEN: &str = "\x01\x1B[32m\x02";
+const YELLOW: &str = "\x01\x1B[33m\x02";
+const BLUE: &str = "\x01\x1B[34m\x02";
+const MAGENTA: &str = "\x01\x1B[35m\x02";
+const CYAN: &str = "\x01\x1B[36m\x02";
+const WHITE: &str = "\x01\x1B[37m\x02";
+const GREY: &str = "\x01\x1B[90m\x02";
+const BOLD: &str = "\x01\x1B[1m\x02";
+const DIM: &str = "\x01\x1B[2m\x02";
+const UNDERLINE: &str = "\x01\x1B[4m\x02";
 const RESET: &str = "\x01\x1B[0m\x02";
 
-pub fn green(s: &str) -> String {
-    return format!("{}{}{}", GREEN, s, RESET);
+/// The wrapped escape sequence of a color (or `bold`, `dim`, `underline`,
+/// `reset`) by name.
+pub fn seq(name: &str) -> Option<&'static str> {
+    match name {
+        "black" => Some(BLACK),
+        "red" => Some(RED),
+        "green" => Some(GREEN),
+        "yellow" => Some(YELLOW),
+        "blue" => Some(BLUE),
+        "magenta" => Some(MAGENTA),
+        "cyan" => Some(CYAN),
+        "white" => Some(WHITE),
+        "grey" => Some(GREY),
+        "bold" => Some(BOLD),
+        "dim" => Some(DIM),
+        "underline" => Some(UNDERLINE),
+        "reset" => Some(RESET),
+        _ => None,
+    }
 }
 
-pub fn red(s: &str) -> String {
-    return format!("{}{}{}", RED, s, RESET);
+/// The raw escape sequences of colors named in `names`, e.g. `bold green`,
+/// for drawing outside of the prompt.
+pub fn raw_seq(names: &str) -> String {
+    let mut result = String::new();
+    for name in names.split([' ', ',']) {
+        if let Some(x) = seq(name) {
+            result.push_str(&x[1..x.len() - 1]);
+        }
+    }
+    result
 }
 
 pub fn blue(s: &str) -> String {
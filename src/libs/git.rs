@@ -0,0 +1,228 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tools;
+
+/// Milliseconds to wait for `git status` before the prompt shows the
+/// branch only; can be changed with `$PROMPT_GIT_TIMEOUT`.
+const DEFAULT_TIMEOUT: u64 = 20;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GitStatus {
+    /// branch name, or the short sha when HEAD is detached
+    pub head: String,
+    /// e.g. `REBASE` or `MERGING`
+    pub state: String,
+    pub staged: bool,
+    pub dirty: bool,
+    pub untracked: bool,
+    pub ahead: u32,
+    pub behind: u32,
+}
+
+/// The prompt segment of the git repo we are in, e.g.
+/// ` (master *+? ↑1|MERGING)`, or empty if not in a repo.
+pub fn get_segment(timeout: Option<String>) -> String {
+    let cwd = match env::current_dir() {
+        Ok(x) => x,
+        Err(_) => return String::new(),
+    };
+    let git_dir = match find_git_dir(&cwd) {
+        Some(x) => x,
+        None => return String::new(),
+    };
+    let timeout = timeout
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_TIMEOUT);
+    let mut status = GitStatus {
+        head: read_head(&git_dir),
+        state: read_state(&git_dir),
+        ..Default::default()
+    };
+    if let Some(output) = run_git_status(timeout) {
+        parse_status(&output, &mut status);
+    }
+    format_segment(&status)
+}
+
+/// Walk up from `dir` to find the `.git` dir. A `.git` file (used by
+/// worktrees and submodules) points to the real one.
+pub fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    for parent in dir.ancestors() {
+        let dot_git = parent.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        if dot_git.is_file() {
+            let content = fs::read_to_string(&dot_git).ok()?;
+            let path = content.trim().strip_prefix("gitdir:")?.trim();
+            return Some(parent.join(path));
+        }
+    }
+    None
+}
+
+fn read_file(path: &Path) -> String {
+    match fs::read_to_string(path) {
+        Ok(x) => x.trim().to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// The current branch from `HEAD`, or the short sha if it's detached.
+/// Rebases detach HEAD, so their branch is read from the rebase dirs.
+pub fn read_head(git_dir: &Path) -> String {
+    for dir in &["rebase-merge", "rebase-apply"] {
+        let head_name = read_file(&git_dir.join(dir).join("head-name"));
+        if let Some(x) = head_name.strip_prefix("refs/heads/") {
+            return x.to_string();
+        }
+    }
+    let head = read_file(&git_dir.join("HEAD"));
+    if let Some(x) = head.strip_prefix("ref: ") {
+        return x.trim_start_matches("refs/heads/").to_string();
+    }
+    head.chars().take(7).collect()
+}
+
+/// The operation in progress, e.g. `REBASE`, or empty if none.
+pub fn read_state(git_dir: &Path) -> String {
+    let state = if git_dir.join("rebase-merge").is_dir() {
+        "REBASE"
+    } else if git_dir.join("rebase-apply").is_dir() {
+        if git_dir.join("rebase-apply/applying").exists() {
+            "AM"
+        } else {
+            "REBASE"
+        }
+    } else if git_dir.join("MERGE_HEAD").exists() {
+        "MERGING"
+    } else if git_dir.join("CHERRY_PICK_HEAD").exists() {
+        "CHERRY-PICKING"
+    } else if git_dir.join("REVERT_HEAD").exists() {
+        "REVERTING"
+    } else if git_dir.join("BISECT_LOG").exists() {
+        "BISECTING"
+    } else {
+        ""
+    };
+    state.to_string()
+}
+
+/// Run `git status`, giving up after `timeout` milliseconds so that
+/// a big repo or a slow file system never blocks the prompt.
+fn run_git_status(timeout: u64) -> Option<String> {
+    let mut cmd = Command::new("git");
+    cmd.args([
+        "status",
+        "--porcelain=v2",
+        "--branch",
+        "--no-renames",
+        "--ignore-submodules=dirty",
+    ])
+    // do not take the index lock of other git commands
+    .env("GIT_OPTIONAL_LOCKS", "0");
+    tools::run_with_timeout(&mut cmd, timeout)
+}
+
+/// Read the outputs of `git status --porcelain=v2 --branch`.
+pub fn parse_status(output: &str, status: &mut GitStatus) {
+    for line in output.lines() {
+        if let Some(ab) = line.strip_prefix("# branch.ab ") {
+            for x in ab.split_whitespace() {
+                if let Some(n) = x.strip_prefix('+') {
+                    status.ahead = n.parse().unwrap_or(0);
+                } else if let Some(n) = x.strip_prefix('-') {
+                    status.behind = n.parse().unwrap_or(0);
+                }
+            }
+        } else if line.starts_with("? ") {
+            status.untracked = true;
+        } else if line.starts_with("u ") {
+            status.staged = true;
+            status.dirty = true;
+        } else if line.starts_with("1 ") || line.starts_with("2 ") {
+            let xy: Vec<char> = line.chars().skip(2).take(2).collect();
+            if xy.len() == 2 {
+                status.staged |= xy[0] != '.';
+                status.dirty |= xy[1] != '.';
+            }
+        }
+    }
+}
+
+fn format_segment(status: &GitStatus) -> String {
+    let mut flags = String::new();
+    if status.dirty {
+        flags.push('*');
+    }
+    if status.staged {
+        flags.push('+');
+    }
+    if status.untracked {
+        flags.push('?');
+    }
+    if status.ahead > 0 {
+        flags.push_str(&format!("↑{}", status.ahead));
+    }
+    if status.behind > 0 {
+        flags.push_str(&format!("↓{}", status.behind));
+    }
+    let mut result = format!(" ({}", status.head);
+    if !flags.is_empty() {
+        result.push(' ');
+        result.push_str(&flags);
+    }
+    if !status.state.is_empty() {
+        result.push('|');
+        result.push_str(&status.state);
+    }
+    result.push(')');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::find_git_dir;
+    use super::format_segment;
+    use super::parse_status;
+    use super::read_head;
+    use super::read_state;
+    use super::GitStatus;
+    use libs::test_dir::TestDir;
+
+    #[test]
+    fn test_git_status() {
+        let dir = TestDir::new("git-status");
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("rebase-merge")).unwrap();
+        fs::create_dir_all(dir.join("src/foo")).unwrap();
+        fs::write(git_dir.join("HEAD"), "1234567890abcdef\n").unwrap();
+        assert_eq!(find_git_dir(&dir.join("src/foo")), Some(git_dir.clone()));
+        assert_eq!(read_head(&git_dir), "1234567");
+        assert_eq!(read_state(&git_dir), "REBASE");
+
+        fs::write(git_dir.join("rebase-merge/head-name"), "refs/heads/dev\n").unwrap();
+        assert_eq!(read_head(&git_dir), "dev");
+        fs::remove_dir_all(git_dir.join("rebase-merge")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(git_dir.join("MERGE_HEAD"), "").unwrap();
+        assert_eq!(read_head(&git_dir), "master");
+        assert_eq!(read_state(&git_dir), "MERGING");
+
+        let output = "# branch.oid abc\n# branch.ab +2 -0\n\
+                      1 .M N... 100644 100644 100644 a b README.md\n? new.txt\n";
+        let mut status = GitStatus {
+            head: "master".to_string(),
+            state: "MERGING".to_string(),
+            ..Default::default()
+        };
+        parse_status(output, &mut status);
+        assert!(status.dirty && status.untracked && !status.staged);
+        assert_eq!(format_segment(&status), " (master *?↑2|MERGING)");
+    }
+}
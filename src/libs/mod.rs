@@ -1,4 +1,7 @@
 pub mod colored;
+pub mod git;
 pub mod os_type;
 pub mod prompt;
 pub mod re;
+#[cfg(test)]
+pub mod test_dir;
@@ -1,82 +1,260 @@
+use libc;
 use libs;
 use std::env;
-use std::error::Error;
+use std::mem;
+use time;
+
+use shell;
 use tools;
 
-pub fn get_prompt(status: i32) -> String {
-    let home = tools::get_user_home();
-    let user;
-    match env::var("USER") {
-        Ok(x) => user = x,
-        Err(e) => {
-            println!("cicada: env USER error: {:?}", e);
-            return String::from("cicada >> ");
-        }
+/// Used when `$PROMPT` is not set: `user@host: dir$ ` in green, or red
+/// if the last command failed, with the virtualenv name in front.
+const DEFAULT_PROMPT: &str = "\\v\\[status]\\u\\[reset]@\\[status]\\h\\[reset]: \\[status]\\W\\[reset]$ ";
+
+/// Render the prompt from the `$PROMPT` template. Escapes are:
+///
+/// - `\u`: user name, `\h`: host name
+/// - `\w`: current dir, `\W`: its last part, both with `~` for home
+/// - `\?`: exit status of the last command
+/// - `\t`: current time as `HH:MM:SS`
+/// - `\D`: how long the last command took
+/// - `\j`: number of background jobs
+/// - `\v`: `(name)` of the active virtualenv, if any
+/// - `\g`: ` (branch)` and status of the git repo, if any
+/// - `\$`: `#` for root, otherwise `$`
+/// - `\n`: a newline, `\\`: a backslash
+/// - `\[color]`: start a color, `bold` or `reset`; `\[status]` is green
+///   if the last command succeeded, otherwise red
+///
+/// `$PROMPT` may have several lines, the input goes after the last one.
+pub fn get_prompt(sh: &shell::Shell) -> String {
+    let template = match sh.get_env("PROMPT") {
+        Some(ref x) if !x.is_empty() => x.clone(),
+        _ => DEFAULT_PROMPT.to_string(),
+    };
+    render(sh, &template)
+}
+
+/// The right prompt, drawn by `text` put after the prompt, and the
+/// column on the input line it starts at.
+#[derive(Debug, PartialEq)]
+pub struct RightPrompt {
+    pub text: String,
+    pub start: usize,
+}
+
+/// `$RPROMPT` rendered like `$PROMPT`, to be shown at the right side of
+/// the input line after `prompt`. None if it's not set or does not fit.
+pub fn get_rprompt(sh: &shell::Shell, prompt: &str) -> Option<RightPrompt> {
+    let template = sh.get_env("RPROMPT")?;
+    if template.is_empty() {
+        return None;
     }
-    let hostname = tools::get_hostname();
-    let _current_dir;
-    match env::current_dir() {
-        Ok(x) => _current_dir = x,
-        Err(e) => {
-            println!("cicada: env current_dir error: {}", e.description());
-            return format!("({})$ ", libs::colored::red("no current dir"));
+    let rprompt = render(sh, &template).replace('\n', " ");
+    right_align(prompt, &rprompt, get_term_width())
+}
+
+/// The width of text on screen, i.e. without the `\x01`..`\x02`
+/// wrapped parts.
+pub fn visible_width(text: &str) -> usize {
+    let mut hidden = false;
+    let mut width = 0;
+    for c in text.chars() {
+        match c {
+            '\x01' => hidden = true,
+            '\x02' => hidden = false,
+            _ if !hidden => width += 1,
+            _ => {}
         }
     }
-    let current_dir;
-    match _current_dir.to_str() {
-        Some(x) => current_dir = x,
-        None => {
-            println!("cicada: to_str error");
-            return String::from("cicada >> ");
-        }
+    width
+}
+
+/// Draw `rprompt` at the right end of the last line of `prompt`, as an
+/// invisible sequence that puts the cursor back, so that linefeed sees
+/// nothing. It's positioned from the right edge of the screen, thus
+/// redraws after resizing the terminal still put it there. Once the
+/// input reaches it, the prompt is set again without it.
+fn right_align(prompt: &str, rprompt: &str, term_width: usize) -> Option<RightPrompt> {
+    let last_line = prompt.rsplit('\n').next().unwrap_or("");
+    let width = visible_width(rprompt);
+    if width == 0 || visible_width(last_line) + width + 1 >= term_width {
+        return None;
     }
-    let _tokens: Vec<&str> = current_dir.split('/').collect();
-
-    let last;
-    match _tokens.last() {
-        Some(x) => last = x,
-        None => {
-            println!("cicada: prompt token last error");
-            return String::from("cicada >> ");
-        }
+    let mut result = String::from("\x01\x1B7\x1B[999C");
+    if width > 1 {
+        result.push_str(&format!("\x1B[{}D", width - 1));
     }
-    let pwd: String;
-    if last.is_empty() {
-        pwd = String::from("/");
-    } else if current_dir == home {
-        pwd = String::from("~");
-    } else {
-        pwd = last.to_string();
+    // it's inside one invisible part already
+    result.push_str(&rprompt.replace(['\x01', '\x02'], ""));
+    result.push_str("\x1B[0m\x1B8\x02");
+    Some(RightPrompt {
+        text: result,
+        start: term_width - width,
+    })
+}
+
+pub fn get_term_width() -> usize {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let ret = unsafe { libc::ioctl(0, libc::TIOCGWINSZ, &mut size) };
+    if ret != 0 {
+        return 0;
     }
+    size.ws_col as usize
+}
 
-    let mut prompt = if status == 0 {
-        format!(
-            "{}@{}: {}$ ",
-            libs::colored::green(user.as_str()),
-            libs::colored::green(hostname.as_str()),
-            libs::colored::green(pwd.as_str())
-        )
-    } else {
-        format!(
-            "{}@{}: {}$ ",
-            libs::colored::red(user.as_str()),
-            libs::colored::red(hostname.as_str()),
-            libs::colored::red(pwd.as_str())
-        )
-    };
-    if let Ok(x) = env::var("VIRTUAL_ENV") {
-        if x != "" {
-            let _tokens: Vec<&str> = x.split('/').collect();
-            let env_name;
-            match _tokens.last() {
-                Some(x) => env_name = x,
-                None => {
-                    println!("prompt token last error");
-                    return String::from("cicada >> ");
+fn render(sh: &shell::Shell, template: &str) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escape = match chars.next() {
+            Some(x) => x,
+            None => {
+                result.push('\\');
+                break;
+            }
+        };
+        match escape {
+            'u' => result.push_str(&get_user()),
+            'h' => result.push_str(&tools::get_hostname()),
+            'w' => result.push_str(&get_cwd(false)),
+            'W' => result.push_str(&get_cwd(true)),
+            '?' => result.push_str(&sh.previous_status.to_string()),
+            't' => {
+                let now = time::now();
+                result.push_str(&format!(
+                    "{:02}:{:02}:{:02}",
+                    now.tm_hour, now.tm_min, now.tm_sec
+                ));
+            }
+            'D' => result.push_str(&format_duration(sh.cmd_duration)),
+            'j' => result.push_str(&sh.bg_pids.len().to_string()),
+            'v' => result.push_str(&get_virtualenv()),
+            'g' => result.push_str(&libs::git::get_segment(sh.get_env("PROMPT_GIT_TIMEOUT"))),
+            '$' => {
+                let is_root = unsafe { libc::geteuid() == 0 };
+                result.push(if is_root { '#' } else { '$' });
+            }
+            'n' => result.push('\n'),
+            '\\' => result.push('\\'),
+            '[' => {
+                let mut name = String::new();
+                let mut closed = false;
+                for x in chars.by_ref() {
+                    if x == ']' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(x);
+                }
+                let seq = if name == "status" {
+                    libs::colored::seq(if sh.previous_status == 0 { "green" } else { "red" })
+                } else {
+                    libs::colored::seq(&name)
+                };
+                match seq {
+                    Some(x) if closed => result.push_str(x),
+                    _ => {
+                        result.push_str("\\[");
+                        result.push_str(&name);
+                        if closed {
+                            result.push(']');
+                        }
+                    }
                 }
             }
-            prompt = format!("({}){}", libs::colored::blue(env_name), prompt);
+            _ => {
+                result.push('\\');
+                result.push(escape);
+            }
+        }
+    }
+    result
+}
+
+fn get_user() -> String {
+    match env::var("USER") {
+        Ok(x) => x,
+        Err(_) => String::from("?"),
+    }
+}
+
+/// The current dir with home shown as `~`; only the last part of it
+/// if `short` is true.
+fn get_cwd(short: bool) -> String {
+    let current_dir = match env::current_dir() {
+        Ok(x) => x.to_string_lossy().into_owned(),
+        Err(_) => return String::from("?"),
+    };
+    let home = tools::get_user_home();
+    if !home.is_empty() && current_dir == home {
+        return String::from("~");
+    }
+    if short {
+        return match current_dir.rsplit('/').next() {
+            Some(x) if !x.is_empty() => x.to_string(),
+            _ => String::from("/"),
+        };
+    }
+    let home_prefix = format!("{}/", home.trim_end_matches('/'));
+    if !home.is_empty() && current_dir.starts_with(&home_prefix) {
+        return format!("~/{}", &current_dir[home_prefix.len()..]);
+    }
+    current_dir
+}
+
+fn get_virtualenv() -> String {
+    match env::var("VIRTUAL_ENV") {
+        Ok(ref x) if !x.is_empty() => {
+            let name = x.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+            format!("({})", libs::colored::blue(name))
         }
+        _ => String::new(),
+    }
+}
+
+/// e.g. `320ms`, `2.5s` and `3m12s`
+fn format_duration(seconds: f64) -> String {
+    if seconds < 1.0 {
+        format!("{}ms", (seconds * 1000.0) as u64)
+    } else if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else {
+        let seconds = seconds as u64;
+        format!("{}m{}s", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, render, right_align, RightPrompt};
+    use shell::Shell;
+
+    #[test]
+    fn test_render() {
+        let mut sh = Shell::new();
+        sh.previous_status = 2;
+        sh.cmd_duration = 2.54;
+        assert_eq!(render(&sh, "[\\?] \\D\\n> "), "[2] 2.5s\n> ");
+        assert_eq!(render(&sh, "\\[red]x\\[reset]"), "\x01\x1B[31m\x02x\x01\x1B[0m\x02");
+        assert_eq!(render(&sh, "\\[status]"), "\x01\x1B[31m\x02");
+        assert_eq!(render(&sh, "\\[nope] \\x \\\\ \\j"), "\\[nope] \\x \\ 0");
+
+        let rprompt = right_align("x\n\x01\x1B[31m\x02ab$ ", "\x01\x1B[32m\x0212", 10);
+        assert_eq!(
+            rprompt,
+            Some(RightPrompt {
+                text: "\x01\x1B7\x1B[999C\x1B[1D\x1B[32m12\x1B[0m\x1B8\x02".to_string(),
+                start: 8,
+            })
+        );
+        assert_eq!(right_align("abcdefg$ ", "12", 10), None);
+
+        assert_eq!(format_duration(0.32), "320ms");
+        assert_eq!(format_duration(192.0), "3m12s");
     }
-    prompt
 }
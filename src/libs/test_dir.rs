@@ -0,0 +1,43 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A temporary directory for tests, which is removed when it goes out
+/// of scope, also when an assert fails before the end of the test.
+pub struct TestDir {
+    root: PathBuf,
+}
+
+impl TestDir {
+    /// Create an empty directory like `/tmp/cicada-test-NAME-PID`.
+    pub fn new(name: &str) -> TestDir {
+        let root = env::temp_dir().join(format!("cicada-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("error when create test dir");
+        TestDir { root }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Write a file under the directory, creating its parents.
+    pub fn write<P: AsRef<Path>>(&self, path: P, content: &str) {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("error when create test dir");
+        }
+        fs::write(&path, content).expect("error when write test file");
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
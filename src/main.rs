@@ -13,8 +13,11 @@ extern crate yaml_rust;
 extern crate nom;
 
 use std::env;
+use std::io;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
+use linefeed::terminal::DefaultTerminal;
 use linefeed::{Interface, ReadResult};
 
 mod types;
@@ -22,11 +25,14 @@ mod types;
 mod tools;
 mod builtins;
 mod completers;
+mod editor;
 mod execute;
+mod highlight;
 mod history;
 mod libs;
 mod parsers;
 mod rcfile;
+mod scripting;
 mod shell;
 
 use tools::clog;
@@ -36,6 +42,9 @@ pub use tools::CommandResult;
 #[allow(cast_lossless)]
 fn main() {
     let mut sh = shell::Shell::new();
+    if let Ok(x) = env::current_dir() {
+        env::set_var("PWD", x);
+    }
     rcfile::load_rcfile(&mut sh);
 
     // this section handles `cicada -c 'echo hi && echo yoo'`,
@@ -43,7 +52,9 @@ fn main() {
     if env::args().len() > 1 {
         let line = tools::env_args_to_command_line();
         log!("run with -c args: {}", &line);
+        sh.lineno = 1;
         execute::run_procs(&mut sh, &line, false);
+        scripting::run_hook(&mut sh, "on_exit", Vec::new());
         return;
     }
 
@@ -52,6 +63,7 @@ fn main() {
         // cases like open a new MacVim window,
         // (i.e. CMD+N) on an existing one
         execute::handle_non_tty(&mut sh);
+        scripting::run_hook(&mut sh, "on_exit", Vec::new());
         return;
     }
 
@@ -64,37 +76,64 @@ fn main() {
             return;
         }
     }
+    sh.interactive = true;
+    shell::catch_sigint();
     history::init(&mut rl);
-    rl.set_completer(Arc::new(completers::CicadaCompleter {
-        sh: Arc::new(sh.clone()),
-    }));
+    let edit_state = editor::init(&rl);
 
-    let mut status = 0;
     loop {
-        let prompt = libs::prompt::get_prompt(status);
-        match rl.set_prompt(&prompt) {
+        sh.reap_jobs();
+        scripting::run_hook(&mut sh, "precmd", Vec::new());
+        let prompt = libs::prompt::get_prompt(&sh);
+        let rprompt = libs::prompt::get_rprompt(&sh, &prompt);
+        // a snapshot of the shell, so that completions see the aliases,
+        // functions etc. defined so far
+        rl.set_completer(Arc::new(completers::CicadaCompleter {
+            sh: Arc::new(sh.clone()),
+        }));
+        highlight::clear_path_cache();
+        let full_prompt = match rprompt {
+            Some(ref x) => format!("{}{}", prompt, x.text),
+            None => prompt.clone(),
+        };
+        match rl.set_prompt(&full_prompt) {
             Ok(_) => {}
             Err(e) => {
                 println!("error when setting prompt: {:?}\n", e);
             }
         }
-        match rl.read_line() {
+        match read_line(&rl, &sh, &edit_state, &prompt, rprompt.as_ref()) {
             Ok(ReadResult::Input(line)) => {
                 if line.trim() == "" {
                     continue;
                 }
+                let mut line = line;
+                if !read_continuation(&rl, &sh, &edit_state, &mut line) {
+                    continue;
+                }
                 sh.cmd = line.clone();
+                sh.lineno += 1;
 
                 let tsb_spec = time::get_time();
                 let tsb = (tsb_spec.sec as f64) + tsb_spec.nsec as f64 / 1_000_000_000.0;
 
+                let cwd = match env::current_dir() {
+                    Ok(x) => x.to_string_lossy().into_owned(),
+                    Err(_) => String::new(),
+                };
                 let mut line = line.clone();
-                tools::extend_bandband(&sh, &mut line);
-                status = execute::run_procs(&mut sh, &line, true);
+                if !tools::extend_bandband(&sh, &mut line) {
+                    continue;
+                }
+                scripting::run_hook(&mut sh, "preexec", vec![line.clone()]);
+                shell::INTERRUPTED.store(false, Ordering::SeqCst);
+                let status = execute::run_procs(&mut sh, &line, true);
+                sh.previous_status = status;
 
                 let tse_spec = time::get_time();
                 let tse = (tse_spec.sec as f64) + tse_spec.nsec as f64 / 1_000_000_000.0;
-                history::add(&mut sh, &mut rl, &line, status, tsb, tse);
+                sh.cmd_duration = tse - tsb;
+                history::add(&mut sh, &mut rl, &line, &cwd, status, tsb, tse);
             }
             Ok(ReadResult::Eof) => {
                 if let Ok(x) = env::var("NO_EXIT_ON_CTRL_D") {
@@ -104,6 +143,7 @@ fn main() {
                     }
                 }
                 println!("exit");
+                scripting::run_hook(&mut sh, "on_exit", Vec::new());
                 break;
             }
             Ok(ReadResult::Signal(s)) => {
@@ -115,3 +155,69 @@ fn main() {
         }
     }
 }
+
+/// Read a line step by step, so that it can be highlighted, and the
+/// suggestion shown, after each edit. The right prompt is hidden while
+/// the input reaches it.
+fn read_line(
+    rl: &Interface<DefaultTerminal>,
+    sh: &shell::Shell,
+    edit_state: &editor::SharedState,
+    prompt: &str,
+    rprompt: Option<&libs::prompt::RightPrompt>,
+) -> io::Result<ReadResult> {
+    let mut rprompt_shown = rprompt.is_some();
+    loop {
+        if let Some(result) = rl.read_line_step(None)? {
+            if let ReadResult::Input(ref line) = result {
+                editor::finish(edit_state, prompt, line);
+            }
+            return Ok(result);
+        }
+        let buffer = rl.buffer();
+        if let Some(rp) = rprompt {
+            let end = editor::input_end(prompt, &buffer);
+            if rprompt_shown && end >= rp.start {
+                rl.set_prompt(prompt)?;
+                editor::clear_line_after(end);
+                rprompt_shown = false;
+            } else if !rprompt_shown && end < rp.start {
+                rl.set_prompt(&format!("{}{}", prompt, rp.text))?;
+                rprompt_shown = true;
+            }
+        }
+        editor::redraw(sh, edit_state, prompt, &buffer);
+    }
+}
+
+/// Keep reading lines with the `> ` prompt while the input is an
+/// unfinished compound command, e.g. `if` without `fi`.
+/// Returns false if the input is abandoned with Ctrl-D.
+fn read_continuation(
+    rl: &Interface<DefaultTerminal>,
+    sh: &shell::Shell,
+    edit_state: &editor::SharedState,
+    line: &mut String,
+) -> bool {
+    while parsers::parser_script::is_incomplete(line) {
+        if let Err(e) = rl.set_prompt("> ") {
+            println!("error when setting prompt: {:?}\n", e);
+        }
+        match read_line(rl, sh, edit_state, "> ", None) {
+            Ok(ReadResult::Input(more)) => {
+                line.push('\n');
+                line.push_str(&more);
+            }
+            Ok(ReadResult::Eof) => {
+                println!();
+                return false;
+            }
+            Ok(ReadResult::Signal(_)) => return false,
+            Err(e) => {
+                println!("readline error: {:?}", e);
+                return false;
+            }
+        }
+    }
+    true
+}
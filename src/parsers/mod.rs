@@ -1,3 +1,4 @@
 pub mod parser_float;
 pub mod parser_int;
 pub mod parser_line;
+pub mod parser_script;
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use regex::Regex;
 
 use tools;
@@ -27,14 +29,27 @@ pub fn tokens_to_args(tokens: &Vec<(String, String)>) -> Vec<String> {
 /// >>> line_to_cmds("man awk | grep version");
 /// vec!["man awk | grep version"]
 pub fn line_to_cmds(line: &str) -> Vec<String> {
+    line_to_cmd_ranges(line)
+        .into_iter()
+        .map(|x| line[x].to_string())
+        .collect()
+}
+
+/// The byte ranges of what `line_to_cmds()` returns, i.e. of the commands
+/// and the separators between them, e.g. to highlight them.
+pub fn line_to_cmd_ranges(line: &str) -> Vec<Range<usize>> {
     // Special characters: http://tldp.org/LDP/abs/html/special-chars.html
     let mut result = Vec::new();
     let mut sep = String::new();
     let mut token = String::new();
-    let len = line.len();
-    for (i, c) in line.chars().enumerate() {
+    // where `token` starts in `line`
+    let mut start = 0;
+    // inside `[[ ... ]]`, where `&&` and `||` are part of the condition
+    let mut in_cond = false;
+    for (i, c) in line.char_indices() {
         if c == '#' {
-            if sep.is_empty() {
+            // only a `#` at the start of a word begins a comment, not `$#`
+            if sep.is_empty() && (token.is_empty() || token.ends_with(char::is_whitespace)) {
                 break;
             } else {
                 token.push(c);
@@ -55,26 +70,28 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
                 continue;
             }
         }
+        let c_next = line[i + c.len_utf8()..].chars().next();
+        if sep.is_empty()
+            && c == '['
+            && c_next == Some('[')
+            && (token.is_empty() || token.ends_with(char::is_whitespace))
+        {
+            in_cond = true;
+        }
+        if in_cond {
+            if c == ']' && token.ends_with(']') {
+                in_cond = false;
+            }
+            token.push(c);
+            continue;
+        }
         if c == '&' || c == '|' {
             // needs watch ahead here
             if sep.is_empty() {
-                if i + 1 == len {
-                    // for bg commands, e.g. `ls &`
+                // a single one is kept, e.g. for bg commands like `ls &`
+                if c_next != Some(c) {
                     token.push(c);
                     continue;
-                } else {
-                    let c_next;
-                    match line.chars().nth(i + 1) {
-                        Some(x) => c_next = x,
-                        None => {
-                            println!("chars nth error - should never happen");
-                            continue;
-                        }
-                    }
-                    if c_next != c {
-                        token.push(c);
-                        continue;
-                    }
                 }
             }
 
@@ -82,12 +99,14 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
                 sep.push(c);
                 continue;
             } else if c.to_string() == sep {
-                let _token = token.trim().to_string();
-                if !_token.is_empty() {
-                    result.push(_token);
+                // the first one is at `i - 1`
+                let range = trimmed_range(line, start, i - 1);
+                if !range.is_empty() {
+                    result.push(range);
                 }
                 token = String::new();
-                result.push(format!("{}{}", sep, sep));
+                result.push(i - 1..i + 1);
+                start = i + 1;
                 sep = String::new();
                 continue;
             } else {
@@ -97,12 +116,13 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
         }
         if c == ';' {
             if sep.is_empty() {
-                let _token = token.trim().to_string();
-                if !_token.is_empty() {
-                    result.push(_token);
+                let range = trimmed_range(line, start, i);
+                if !range.is_empty() {
+                    result.push(range);
                 }
-                result.push(String::from(";"));
+                result.push(i..i + 1);
                 token = String::new();
+                start = i + 1;
                 continue;
             } else {
                 token.push(c);
@@ -112,11 +132,18 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
         token.push(c);
     }
     if !token.is_empty() {
-        result.push(token.trim().to_string());
+        result.push(trimmed_range(line, start, start + token.len()));
     }
     result
 }
 
+/// `start..end` of `line` without the whitespace around it.
+fn trimmed_range(line: &str, start: usize, end: usize) -> Range<usize> {
+    let text = &line[start..end];
+    let begin = start + text.len() - text.trim_start().len();
+    begin..begin + text.trim().len()
+}
+
 /// parse command line to tokens
 /// >>> cmd_to_tokens("echo 'hi yoo' | grep \"hi\"");
 /// vec![
@@ -126,8 +153,18 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
 ///     ("", "grep"),
 ///     ("\"", "hi"),
 /// ]
-#[allow(cyclomatic_complexity)]
 pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
+    cmd_to_token_ranges(line)
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect()
+}
+
+/// The tokens of `cmd_to_tokens()` with the byte ranges they are read
+/// from in `line`, quotes and backslashes included, e.g. to highlight
+/// them.
+#[allow(cyclomatic_complexity)]
+pub fn cmd_to_token_ranges(line: &str) -> Vec<((String, String), Range<usize>)> {
     let mut result = Vec::new();
     let mut sep = String::new();
     // `sep_second` is for commands like this:
@@ -135,19 +172,24 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
     // it only could have non-empty value when sep is empty.
     let mut sep_second = String::new();
     let mut token = String::new();
+    // where `token` starts in `line`, and the last backslash is
+    let mut start = 0;
+    let mut backslash_at = 0;
     let mut has_backslash = false;
     let mut new_round = true;
     let mut skip_next = false;
-    let count_chars = line.chars().count();
-    for (i, c) in line.chars().enumerate() {
+    for (i, c) in line.char_indices() {
         if skip_next {
             skip_next = false;
             continue;
         }
 
-        if c == '\\' && sep != "\'" {
+        // backslashes are literal in single quotes, including the quoted
+        // value of assignments like `PROMPT='\u$ '`
+        if c == '\\' && sep != "\'" && sep_second != "\'" {
             if !has_backslash {
                 has_backslash = true;
+                backslash_at = i;
             } else {
                 has_backslash = false;
                 token.push(c);
@@ -158,7 +200,11 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
         if new_round {
             if c == ' ' {
                 continue;
-            } else if c == '"' || c == '\'' || c == '`' {
+            }
+            if token.is_empty() && c != '|' {
+                start = if has_backslash { backslash_at } else { i };
+            }
+            if c == '"' || c == '\'' || c == '`' {
                 sep = c.to_string();
             } else {
                 sep = String::new();
@@ -174,11 +220,11 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
                 }
 
                 if c == '|' {
-                    if i + 1 < count_chars && line.chars().nth(i + 1).unwrap() == '|' {
-                        result.push((String::from(""), "||".to_string()));
+                    if line[i + 1..].starts_with('|') {
+                        result.push(((String::from(""), "||".to_string()), i..i + 2));
                         skip_next = true;
                     } else {
-                        result.push((String::from(""), "|".to_string()));
+                        result.push(((String::from(""), "|".to_string()), i..i + 1));
                     }
                     new_round = true;
                     continue;
@@ -190,9 +236,9 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
             continue;
         }
 
-        if c == '|' && !has_backslash && sep.is_empty() {
-            result.push((String::from(""), token));
-            result.push((String::from(""), "|".to_string()));
+        if c == '|' && !has_backslash && sep.is_empty() && sep_second.is_empty() {
+            result.push(((String::from(""), token), start..i));
+            result.push(((String::from(""), "|".to_string()), i..i + 1));
             sep = String::new();
             sep_second = String::new();
             token = String::new();
@@ -209,7 +255,7 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
 
             if sep.is_empty() {
                 if sep_second.is_empty() {
-                    result.push((String::from(""), token));
+                    result.push(((String::from(""), token), start..i));
                     token = String::new();
                     new_round = true;
                     continue;
@@ -245,7 +291,7 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
                 }
                 continue;
             } else if sep == c.to_string() {
-                result.push((c.to_string(), token));
+                result.push(((c.to_string(), token), start..i + 1));
                 sep = String::new();
                 sep_second = String::new();
                 token = String::new();
@@ -257,7 +303,7 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
         } else {
             if has_backslash {
                 has_backslash = false;
-                if sep == "\"" || sep == "'" {
+                if sep == "\"" || sep == "'" || sep_second == "\"" {
                     token.push('\\');
                 }
             }
@@ -265,7 +311,7 @@ pub fn cmd_to_tokens(line: &str) -> Vec<(String, String)> {
         }
     }
     if !token.is_empty() {
-        result.push((sep, token));
+        result.push(((sep, token), start..line.len()));
     }
     result
 }
@@ -311,7 +357,7 @@ pub fn cmd_to_with_redirects(tokens: &Tokens) -> Result<Command, String> {
 
         let ptn1 = r"^([^>]*)(>>?)([^>]+)$";
         let ptn2 = r"^([^>]*)(>>?)$";
-        if !tools::re_contains(word, r">") {
+        if !has_unquoted_gt(word) {
             tokens_new.push(token.clone());
         } else if tools::re_contains(word, ptn1) {
             let re;
@@ -370,6 +416,22 @@ pub fn cmd_to_with_redirects(tokens: &Tokens) -> Result<Command, String> {
     })
 }
 
+/// Whether the word has a `>` out of quotes; the quotes of words like
+/// `X='<a@b.c>'` are kept in the tokens, and such `>` is no redirection.
+fn has_unquoted_gt(word: &str) -> bool {
+    let mut quote: Option<char> = None;
+    for c in word.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '>' => return true,
+            None => {}
+        }
+    }
+    false
+}
+
 #[allow(dead_code)]
 fn is_valid_cmd(cmd: &str) -> bool {
     if let Some(c) = cmd.chars().nth(0) {
@@ -476,6 +538,7 @@ pub fn unquote(text: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::cmd_to_tokens;
+    use super::cmd_to_with_redirects;
     use super::is_valid_input;
     use super::line_to_cmds;
     use super::line_to_plain_tokens;
@@ -547,6 +610,7 @@ mod tests {
                 "export FOO=\"`date` and `go version`\"",
                 vec![("", "export"), ("", "FOO=\"`date` and `go version`\"")],
             ),
+            ("PS='\\u \\$ '", vec![("", "PS='\\u \\$ '")]),
             ("ps|wc", vec![("", "ps"), ("", "|"), ("", "wc")]),
             (
                 "cat foo.txt|sort -n|wc",
@@ -628,6 +692,10 @@ mod tests {
                 "Foo=\"a b c\" ./foo.sh",
                 vec![("", "Foo=\"a b c\""), ("", "./foo.sh")],
             ),
+            (
+                "alias -g G='| grep'",
+                vec![("", "alias"), ("", "-g"), ("", "G='| grep'")],
+            ),
         ];
         for (left, right) in v {
             println!("\ninput: {:?}", left);
@@ -688,6 +756,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cmd_to_with_redirects() {
+        let tokens = cmd_to_tokens("export A='Hugo <w@x.com>' B=\"a>b\" > out.txt");
+        let cmd = cmd_to_with_redirects(&tokens).unwrap();
+        _assert_vec_tuple_eq(
+            cmd.tokens,
+            vec![("", "export"), ("", "A='Hugo <w@x.com>'"), ("", "B=\"a>b\"")],
+        );
+        assert_eq!(
+            cmd.redirects,
+            vec![("1".to_string(), ">".to_string(), "out.txt".to_string())]
+        );
+    }
+
     #[test]
     fn test_line_to_cmds() {
         let v = vec![
@@ -700,6 +782,11 @@ mod tests {
             ),
             ("ls | wc", vec!["ls | wc"]),
             ("echo #foo; echo bar", vec!["echo"]),
+            ("echo $# a#b", vec!["echo $# a#b"]),
+            (
+                "[[ -n $a && ( $b || ! -d c ) ]] && echo ok",
+                vec!["[[ -n $a && ( $b || ! -d c ) ]]", "&&", "echo ok"],
+            ),
             ("echo foo; echo bar", vec!["echo foo", ";", "echo bar"]),
             ("echo 'foo; echo bar'", vec!["echo 'foo; echo bar'"]),
             ("echo \"foo; echo bar\"", vec!["echo \"foo; echo bar\""]),
@@ -0,0 +1,948 @@
+use std::ops::Range;
+
+use regex::Regex;
+
+use tools;
+use types::Stmt;
+
+/// Keywords that can not start a command on their own.
+const RESERVED: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Keywords starting compound commands, which may be piped and redirected.
+const COMPOUNDS: &[&str] = &["if", "for", "while", "until", "case"];
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The script is not finished yet, e.g. `fi` is missing.
+    Incomplete,
+    /// A syntax error message, and the line number where it occurs.
+    Syntax(String, usize),
+}
+
+/// A piece of script text ended by `;`, `;;`, a new line or the end of text.
+#[derive(Debug)]
+struct Piece {
+    text: String,
+    term: String,
+    lineno: usize,
+    /// where it is in the text, before its terminator
+    range: Range<usize>,
+}
+
+/// Split script text into pieces on `;` and new lines that are not quoted,
+/// dropping comments and joining escaped new lines. A line ending with
+/// `&&`, `||` or `|` is continued on the next line.
+fn split_pieces(text: &str, first_lineno: usize) -> Vec<Piece> {
+    let chars: Vec<char> = text.chars().collect();
+    // byte offsets of the chars
+    let offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut piece = String::new();
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    let mut lineno = first_lineno;
+    let mut piece_lineno = lineno;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = if i + 1 < chars.len() { chars[i + 1] } else { '\0' };
+        if piece.trim().is_empty() && !c.is_whitespace() {
+            piece_lineno = lineno;
+        }
+        i += 1;
+        if quote == Some('\'') {
+            if c == '\'' {
+                quote = None;
+            } else if c == '\n' {
+                lineno += 1;
+            }
+            piece.push(c);
+            continue;
+        }
+        if c == '\\' {
+            if next == '\n' {
+                lineno += 1;
+            } else {
+                piece.push(c);
+                if next != '\0' {
+                    piece.push(next);
+                }
+            }
+            i += 1;
+            continue;
+        }
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            } else if c == '\n' {
+                lineno += 1;
+            }
+            piece.push(c);
+            continue;
+        }
+        match c {
+            '\'' | '"' | '`' => {
+                quote = Some(c);
+                piece.push(c);
+            }
+            '$' if next == '(' => {
+                depth += 1;
+                piece.push_str("$(");
+                i += 1;
+            }
+            '(' if depth > 0 => {
+                depth += 1;
+                piece.push(c);
+            }
+            ')' if depth > 0 => {
+                depth -= 1;
+                piece.push(c);
+            }
+            '#' if depth == 0 && (piece.is_empty() || piece.ends_with(char::is_whitespace)) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '\n' => {
+                lineno += 1;
+                let tail = piece.trim_end().to_string();
+                if depth > 0 || tail.ends_with("&&") || tail.ends_with("||") || tail.ends_with('|') {
+                    piece.push(' ');
+                } else {
+                    pieces.push(Piece {
+                        text: piece.trim().to_string(),
+                        term: "\n".to_string(),
+                        lineno: piece_lineno,
+                        range: start..offsets[i - 1],
+                    });
+                    piece = String::new();
+                    start = offsets[i - 1] + 1;
+                }
+            }
+            ';' if depth == 0 => {
+                let end = offsets[i - 1];
+                let term = if next == ';' {
+                    i += 1;
+                    ";;"
+                } else {
+                    ";"
+                };
+                pieces.push(Piece {
+                    text: piece.trim().to_string(),
+                    term: term.to_string(),
+                    lineno: piece_lineno,
+                    range: start..end,
+                });
+                piece = String::new();
+                start = end + term.len();
+            }
+            _ => piece.push(c),
+        }
+    }
+    pieces.push(Piece {
+        text: piece.trim().to_string(),
+        term: String::new(),
+        lineno: piece_lineno,
+        range: start..text.len(),
+    });
+    pieces
+}
+
+/// The byte ranges of the pieces of `text` split like scripts are, and
+/// of the `;`, `;;` and new lines ending them, e.g. to highlight it.
+pub fn piece_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut result = Vec::new();
+    for piece in split_pieces(text, 1) {
+        let end = piece.range.end;
+        result.push(piece.range);
+        if !piece.term.is_empty() {
+            result.push(end..end + piece.term.len());
+        }
+    }
+    result
+}
+
+fn first_word(text: &str) -> &str {
+    text.split_whitespace().next().unwrap_or("")
+}
+
+/// Like `first_word()`, but quoted spaces and the ones in `$(...)` are
+/// kept in the word, e.g. `"a b"` of `"a b" in`.
+fn first_shell_word(text: &str) -> &str {
+    let text = text.trim_start();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        if let Some(q) = quote {
+            if c == '\\' && q != '\'' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '\'' | '"' | '`' => quote = Some(c),
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if c.is_whitespace() && depth <= 0 => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Match the header of function definitions like `name() {` or
+/// `function name {`, returning the name and the text after the header.
+fn match_func_header(text: &str) -> Option<(String, String)> {
+    let re = match Regex::new(
+        r"^(function\s+)?([a-zA-Z_][a-zA-Z0-9_-]*)\s*(\(\s*\))?\s*(.*)$",
+    ) {
+        Ok(x) => x,
+        Err(_) => return None,
+    };
+    let caps = re.captures(text)?;
+    if caps.get(1).is_none() && caps.get(3).is_none() {
+        return None;
+    }
+    let rest = caps.get(4).map_or("", |m| m.as_str());
+    Some((caps[2].to_string(), rest.to_string()))
+}
+
+/// Split the patterns of a case arm like `(a|"b c")  echo yes` on the
+/// unquoted `|`, returning them and the text after the `)`.
+fn split_case_patterns(text: &str) -> Option<(Vec<String>, String)> {
+    let text = text.trim_start();
+    let text = text.strip_prefix('(').unwrap_or(text);
+    let mut patterns = Vec::new();
+    let mut pattern = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            pattern.push(c);
+            continue;
+        }
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            pattern.push(c);
+            continue;
+        }
+        match c {
+            '\\' => {
+                escaped = true;
+                pattern.push(c);
+            }
+            '\'' | '"' => {
+                quote = Some(c);
+                pattern.push(c);
+            }
+            '|' | ')' => {
+                if pattern.trim().is_empty() {
+                    return None;
+                }
+                patterns.push(pattern.trim().to_string());
+                pattern = String::new();
+                if c == ')' {
+                    return Some((patterns, text[i + 1..].trim().to_string()));
+                }
+            }
+            _ => pattern.push(c),
+        }
+    }
+    None
+}
+
+/// Split a command line at the first pipe into a compound command, e.g.
+/// `ls | sort | while read f` -> (`ls | sort`, `while read f`).
+fn split_pipe_to_compound(text: &str) -> Option<(String, String)> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut depth = 0;
+    let mut prev = '\0';
+    let mut iter = text.char_indices().peekable();
+    while let Some((i, c)) = iter.next() {
+        let next = iter.peek().map_or('\0', |x| x.1);
+        if escaped {
+            escaped = false;
+        } else if let Some(q) = quote {
+            if c == '\\' && q != '\'' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else {
+            match c {
+                '\\' => escaped = true,
+                '\'' | '"' | '`' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '|' if depth <= 0 && prev != '|' && next != '|' => {
+                    let head = text[..i].trim();
+                    let rest = text[i + 1..].trim();
+                    if !head.is_empty() && COMPOUNDS.contains(&first_word(rest)) {
+                        return Some((head.to_string(), rest.to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+        prev = c;
+    }
+    None
+}
+
+/// Split what follows a compound command, e.g. `2> err.txt | sort`, into
+/// the redirections and the rest of the pipeline after the first pipe.
+fn split_compound_tail(text: &str) -> (String, Option<String>) {
+    let mut quote: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => quote = Some(c),
+            '|' => {
+                let rest = text[i + 1..].trim().to_string();
+                return (text[..i].trim().to_string(), Some(rest));
+            }
+            _ => {}
+        }
+    }
+    (text.trim().to_string(), None)
+}
+
+/// Join a command to the pipeline after it.
+fn join_pipeline(stmt: Stmt, next: Stmt) -> Stmt {
+    match next {
+        Stmt::Pipeline(mut parts) => {
+            parts.insert(0, stmt);
+            Stmt::Pipeline(parts)
+        }
+        _ => Stmt::Pipeline(vec![stmt, next]),
+    }
+}
+
+struct Parser {
+    pieces: Vec<Piece>,
+    pos: usize,
+}
+
+/// What comes next in a list of statements.
+enum Next {
+    Stmt(Stmt),
+    /// A statement ended by `;;`, which ends the list too.
+    Last(Stmt),
+    /// The keyword of `ends` ending the list, or an empty one at the end
+    /// of text.
+    End(String),
+}
+
+impl Parser {
+    fn lineno(&self) -> usize {
+        match self.pieces.get(self.pos) {
+            Some(p) => p.lineno,
+            None => self.pieces.last().map_or(1, |p| p.lineno),
+        }
+    }
+
+    fn unexpected(&self, token: &str) -> ParseError {
+        ParseError::Syntax(
+            format!("syntax error near unexpected token `{}'", token),
+            self.lineno(),
+        )
+    }
+
+    /// Remove the keyword at the start of the current piece, leaving the
+    /// rest of it as a new statement, e.g. `then echo yes` -> `echo yes`.
+    fn strip_keyword(&mut self, keyword: &str) {
+        let piece = &mut self.pieces[self.pos];
+        let rest = piece.text[keyword.len()..].trim().to_string();
+        piece.text = rest;
+    }
+
+    /// Consume a closing keyword like `}`, which must end its piece.
+    fn close_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        self.strip_keyword(keyword);
+        let rest = self.pieces[self.pos].text.clone();
+        if !rest.is_empty() {
+            return Err(self.unexpected(first_word(&rest)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Skip empty pieces and make sure the next one starts with `keyword`.
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        while self.pos < self.pieces.len() && self.pieces[self.pos].text.is_empty() {
+            self.pos += 1;
+        }
+        if self.pos >= self.pieces.len() {
+            return Err(ParseError::Incomplete);
+        }
+        let word = first_word(&self.pieces[self.pos].text).to_string();
+        if word != keyword {
+            return Err(self.unexpected(&word));
+        }
+        self.strip_keyword(keyword);
+        Ok(())
+    }
+
+    /// Parse statements until one of the keywords in `ends`, which is
+    /// returned and left for the caller to consume. With empty `ends`
+    /// statements are parsed to the end of text.
+    fn parse_list(&mut self, ends: &[&str]) -> Result<(Vec<Stmt>, String), ParseError> {
+        let mut list = Vec::new();
+        loop {
+            match self.parse_next(ends)? {
+                Next::Stmt(stmt) => list.push(stmt),
+                Next::Last(stmt) => {
+                    list.push(stmt);
+                    return Ok((list, ";;".to_string()));
+                }
+                Next::End(end) => return Ok((list, end)),
+            }
+        }
+    }
+
+    /// Parse the next statement of a list, see `parse_list()`.
+    fn parse_next(&mut self, ends: &[&str]) -> Result<Next, ParseError> {
+        loop {
+            if self.pos >= self.pieces.len() {
+                if ends.is_empty() {
+                    return Ok(Next::End(String::new()));
+                }
+                return Err(ParseError::Incomplete);
+            }
+            if !self.pieces[self.pos].text.is_empty() {
+                break;
+            }
+            if self.pieces[self.pos].term == ";;" {
+                if ends.contains(&";;") {
+                    self.pos += 1;
+                    return Ok(Next::End(";;".to_string()));
+                }
+                return Err(self.unexpected(";;"));
+            }
+            self.pos += 1;
+        }
+        let text = self.pieces[self.pos].text.clone();
+        if let Some((name, rest)) = match_func_header(&text) {
+            return Ok(Next::Stmt(self.parse_func(name, rest)?));
+        }
+        let word = first_word(&text);
+        if ends.contains(&word) {
+            return Ok(Next::End(word.to_string()));
+        }
+        if RESERVED.contains(&word) {
+            return Err(self.unexpected(word));
+        }
+        let stmt = self.parse_command()?;
+        // `;;` after a statement ends the arm of a case
+        if self.pieces[self.pos - 1].term == ";;" {
+            if ends.contains(&";;") {
+                return Ok(Next::Last(stmt));
+            }
+            self.pos -= 1;
+            return Err(self.unexpected(";;"));
+        }
+        Ok(Next::Stmt(stmt))
+    }
+
+    /// Parse a command line or a compound command at the current piece,
+    /// with the pipes and redirections around compound commands, e.g.
+    /// `ls | while read f; do ...; done > out.txt`.
+    fn parse_command(&mut self) -> Result<Stmt, ParseError> {
+        let text = self.pieces[self.pos].text.clone();
+        let word = first_word(&text);
+        let stmt = match word {
+            "if" => self.parse_if()?,
+            "for" => self.parse_for()?,
+            "while" | "until" => self.parse_while(word)?,
+            "case" => self.parse_case()?,
+            _ => {
+                let lineno = self.lineno();
+                if let Some((head, rest)) = split_pipe_to_compound(&text) {
+                    self.pieces[self.pos].text = rest;
+                    let next = self.parse_command()?;
+                    return Ok(join_pipeline(Stmt::Cmd(head, lineno), next));
+                }
+                self.pos += 1;
+                return Ok(Stmt::Cmd(text, lineno));
+            }
+        };
+
+        // the closing keyword left what follows it in the piece
+        let tail = self.pieces[self.pos].text.clone();
+        if tail.is_empty() {
+            self.pos += 1;
+            return Ok(stmt);
+        }
+        if !tools::re_contains(&tail, r"^(\d?>|<|\|)") || tail.starts_with("||") {
+            return Err(self.unexpected(first_word(&tail)));
+        }
+        let (redirects, rest) = split_compound_tail(&tail);
+        let stmt = if redirects.is_empty() {
+            stmt
+        } else {
+            Stmt::Redirect(Box::new(stmt), redirects)
+        };
+        let rest = match rest {
+            Some(x) => x,
+            None => {
+                self.pos += 1;
+                return Ok(stmt);
+            }
+        };
+        if rest.is_empty() {
+            // a pipe at the end of a line is continued on the next one
+            if self.pieces[self.pos].term.is_empty() {
+                return Err(ParseError::Incomplete);
+            }
+            return Err(self.unexpected(&self.pieces[self.pos].term.clone()));
+        }
+        if RESERVED.contains(&first_word(&rest)) {
+            return Err(self.unexpected(first_word(&rest)));
+        }
+        self.pieces[self.pos].text = rest;
+        let next = self.parse_command()?;
+        Ok(join_pipeline(stmt, next))
+    }
+
+    /// Parse a non-empty list of statements ended by one of `ends`.
+    fn parse_body(&mut self, ends: &[&str]) -> Result<(Vec<Stmt>, String), ParseError> {
+        let (list, end) = self.parse_list(ends)?;
+        if list.is_empty() {
+            return Err(self.unexpected(&end));
+        }
+        Ok((list, end))
+    }
+
+    fn parse_if(&mut self) -> Result<Stmt, ParseError> {
+        self.strip_keyword("if");
+        let mut branches = Vec::new();
+        let mut else_body = Vec::new();
+        loop {
+            let (cond, _) = self.parse_body(&["then"])?;
+            self.strip_keyword("then");
+            let (body, end) = self.parse_body(&["elif", "else", "fi"])?;
+            branches.push((cond, body));
+            match end.as_str() {
+                "elif" => self.strip_keyword("elif"),
+                "else" => {
+                    self.strip_keyword("else");
+                    let (body, _) = self.parse_body(&["fi"])?;
+                    else_body = body;
+                    self.strip_keyword("fi");
+                    break;
+                }
+                _ => {
+                    self.strip_keyword("fi");
+                    break;
+                }
+            }
+        }
+        Ok(Stmt::If(branches, else_body))
+    }
+
+    /// Parse the `do ... done` body of loops.
+    fn parse_do_body(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        self.expect_keyword("do")?;
+        let (body, _) = self.parse_body(&["done"])?;
+        self.strip_keyword("done");
+        Ok(body)
+    }
+
+    fn parse_for(&mut self) -> Result<Stmt, ParseError> {
+        self.strip_keyword("for");
+        let header = self.pieces[self.pos].text.clone();
+        let name = first_word(&header).to_string();
+        if !tools::re_contains(&name, r"^[a-zA-Z_][a-zA-Z0-9_]*$") {
+            return Err(self.unexpected(&name));
+        }
+        let rest = header[name.len()..].trim().to_string();
+        let words = if rest.is_empty() {
+            None
+        } else if first_word(&rest) == "in" {
+            Some(rest[2..].trim().to_string())
+        } else {
+            return Err(self.unexpected(first_word(&rest)));
+        };
+        self.pos += 1;
+        let body = self.parse_do_body()?;
+        Ok(Stmt::For(name, words, body))
+    }
+
+    fn parse_while(&mut self, keyword: &str) -> Result<Stmt, ParseError> {
+        self.strip_keyword(keyword);
+        let (cond, _) = self.parse_body(&["do"])?;
+        let body = self.parse_do_body()?;
+        if keyword == "until" {
+            Ok(Stmt::Until(cond, body))
+        } else {
+            Ok(Stmt::While(cond, body))
+        }
+    }
+
+    fn parse_func(&mut self, name: String, rest: String) -> Result<Stmt, ParseError> {
+        // the `{` may also start the next line
+        self.pieces[self.pos].text = rest;
+        self.expect_keyword("{")?;
+        let (body, _) = self.parse_body(&["}"])?;
+        self.close_keyword("}")?;
+        Ok(Stmt::Func(name, body))
+    }
+
+    fn parse_case(&mut self) -> Result<Stmt, ParseError> {
+        self.strip_keyword("case");
+        let header = self.pieces[self.pos].text.trim_start().to_string();
+        let word = first_shell_word(&header).to_string();
+        let rest = header[word.len()..].trim().to_string();
+        if word.is_empty() {
+            return Err(self.unexpected(&self.pieces[self.pos].term.clone()));
+        }
+        if first_word(&rest) != "in" {
+            return Err(self.unexpected(first_word(&rest)));
+        }
+        // arms may start on the same line, e.g. `case $x in a) echo a;; esac`
+        self.pieces[self.pos].text = rest[2..].trim().to_string();
+
+        let mut arms = Vec::new();
+        loop {
+            while self.pos < self.pieces.len() && self.pieces[self.pos].text.is_empty() {
+                self.pos += 1;
+            }
+            if self.pos >= self.pieces.len() {
+                return Err(ParseError::Incomplete);
+            }
+            let text = self.pieces[self.pos].text.clone();
+            if first_word(&text) == "esac" {
+                self.strip_keyword("esac");
+                break;
+            }
+            let (patterns, rest) = match split_case_patterns(&text) {
+                Some(x) => x,
+                None => return Err(self.unexpected(first_word(&text))),
+            };
+            self.pieces[self.pos].text = rest;
+            let (body, _) = self.parse_list(&[";;", "esac"])?;
+            arms.push((patterns, body));
+        }
+        Ok(Stmt::Case(word, arms))
+    }
+}
+
+/// Parse script text into statements. Line numbers of the statements
+/// start from `first_lineno`.
+pub fn parse(text: &str, first_lineno: usize) -> Result<Vec<Stmt>, ParseError> {
+    let mut parser = Parser {
+        pieces: split_pieces(text, first_lineno),
+        pos: 0,
+    };
+    let (list, _) = parser.parse_list(&[])?;
+    Ok(list)
+}
+
+/// Parse the text of a script file like `parse()`, but go on after a
+/// syntax error from the next line, so that a bad statement does not
+/// stop the ones after it. An unfinished statement at the end is an
+/// error at the line where it starts.
+pub fn parse_each(text: &str, first_lineno: usize) -> Vec<Result<Stmt, ParseError>> {
+    let mut parser = Parser {
+        pieces: split_pieces(text, first_lineno),
+        pos: 0,
+    };
+    let mut result = Vec::new();
+    loop {
+        while parser.pos < parser.pieces.len() && parser.pieces[parser.pos].text.is_empty() {
+            parser.pos += 1;
+        }
+        let start = parser.pos;
+        let lineno = parser.lineno();
+        match parser.parse_next(&[]) {
+            Ok(Next::Stmt(stmt)) | Ok(Next::Last(stmt)) => result.push(Ok(stmt)),
+            Ok(Next::End(_)) => break,
+            Err(ParseError::Incomplete) => {
+                let msg = "syntax error: unexpected end of file".to_string();
+                result.push(Err(ParseError::Syntax(msg, lineno)));
+                break;
+            }
+            Err(ParseError::Syntax(msg, lineno)) => {
+                result.push(Err(ParseError::Syntax(msg, lineno)));
+                parser.pos = start + 1;
+                while parser.pos < parser.pieces.len() && parser.pieces[parser.pos].lineno <= lineno {
+                    parser.pos += 1;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Whether the text needs more lines to be a complete script,
+/// e.g. `if true; then` without the `fi`.
+pub fn is_incomplete(text: &str) -> bool {
+    parse(text, 1) == Err(ParseError::Incomplete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use super::parse_each;
+    use super::piece_ranges;
+    use super::is_incomplete;
+    use super::ParseError;
+    use types::Stmt;
+
+    fn cmd(s: &str, lineno: usize) -> Stmt {
+        Stmt::Cmd(s.to_string(), lineno)
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse("echo a; echo b && echo c\n\n# comment\necho 'd;#e' # f", 1),
+            Ok(vec![
+                cmd("echo a", 1),
+                cmd("echo b && echo c", 1),
+                cmd("echo 'd;#e'", 4),
+            ])
+        );
+        assert_eq!(
+            parse("echo $(date; date) |\n wc -l \\\n -c", 1),
+            Ok(vec![cmd("echo $(date; date) |  wc -l  -c", 1)])
+        );
+        assert_eq!(parse("echo a#b", 3), Ok(vec![cmd("echo a#b", 3)]));
+    }
+
+    #[test]
+    fn test_parse_if() {
+        assert_eq!(
+            parse("if true; then echo a; fi", 1),
+            Ok(vec![Stmt::If(
+                vec![(vec![cmd("true", 1)], vec![cmd("echo a", 1)])],
+                vec![],
+            )])
+        );
+        let text = "if test -d a\nthen\n  echo a\nelif false; then echo b\nelse\n  if true; then echo c; fi\nfi\necho d";
+        assert_eq!(
+            parse(text, 1),
+            Ok(vec![
+                Stmt::If(
+                    vec![
+                        (vec![cmd("test -d a", 1)], vec![cmd("echo a", 3)]),
+                        (vec![cmd("false", 4)], vec![cmd("echo b", 4)]),
+                    ],
+                    vec![Stmt::If(
+                        vec![(vec![cmd("true", 6)], vec![cmd("echo c", 6)])],
+                        vec![],
+                    )],
+                ),
+                cmd("echo d", 8),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_loops() {
+        assert_eq!(
+            parse("for f in *.log; do gzip $f; done", 1),
+            Ok(vec![Stmt::For(
+                "f".to_string(),
+                Some("*.log".to_string()),
+                vec![cmd("gzip $f", 1)],
+            )])
+        );
+        let text = "for i\ndo\n  while true; do break; done\ndone\nuntil false\ndo echo; done";
+        assert_eq!(
+            parse(text, 1),
+            Ok(vec![
+                Stmt::For(
+                    "i".to_string(),
+                    None,
+                    vec![Stmt::While(vec![cmd("true", 3)], vec![cmd("break", 3)])],
+                ),
+                Stmt::Until(vec![cmd("false", 5)], vec![cmd("echo", 6)]),
+            ])
+        );
+        assert!(is_incomplete("for i in 1 2; do"));
+        assert!(is_incomplete("while true"));
+        assert!(parse("for i in 1 2; echo; done", 1).is_err());
+        assert!(parse("for 1 in a; do echo; done", 1).is_err());
+        assert!(parse("while true; do done", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_case() {
+        let patterns = |v: &[&str]| v.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+        assert_eq!(
+            parse("case $(uname) in Linux) echo a;; Darwin|*BSD) echo b; echo c;; *) ;; esac", 1),
+            Ok(vec![Stmt::Case(
+                "$(uname)".to_string(),
+                vec![
+                    (patterns(&["Linux"]), vec![cmd("echo a", 1)]),
+                    (patterns(&["Darwin", "*BSD"]), vec![cmd("echo b", 1), cmd("echo c", 1)]),
+                    (patterns(&["*"]), vec![]),
+                ],
+            )])
+        );
+        let text = "case $x in\n  (\"a|b\")\n    if true; then echo; fi\n    ;;\n  c) echo c\nesac";
+        assert_eq!(
+            parse(text, 1),
+            Ok(vec![Stmt::Case(
+                "$x".to_string(),
+                vec![
+                    (
+                        patterns(&["\"a|b\""]),
+                        vec![Stmt::If(vec![(vec![cmd("true", 3)], vec![cmd("echo", 3)])], vec![])],
+                    ),
+                    (patterns(&["c"]), vec![cmd("echo c", 5)]),
+                ],
+            )])
+        );
+        assert_eq!(
+            parse("case \"a b\" in \"a b\") echo ab;; esac", 1),
+            Ok(vec![Stmt::Case(
+                "\"a b\"".to_string(),
+                vec![(patterns(&["\"a b\""]), vec![cmd("echo ab", 1)])],
+            )])
+        );
+        assert_eq!(
+            parse("case $(uname -s) in\n*) ;;\nesac", 1),
+            Ok(vec![Stmt::Case("$(uname -s)".to_string(), vec![(patterns(&["*"]), vec![])])])
+        );
+        assert!(is_incomplete("case $x in"));
+        assert!(is_incomplete("case $x in a) echo a;;"));
+        assert!(parse("case $x a) echo;; esac", 1).is_err());
+        assert!(parse("echo a;; echo b", 1).is_err());
+        assert!(parse("case $x in a echo;; esac", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_func() {
+        let func = |name: &str, body: Vec<Stmt>| Stmt::Func(name.to_string(), body);
+        assert_eq!(
+            parse("greet() { echo hi $1; }\ngreet you", 1),
+            Ok(vec![func("greet", vec![cmd("echo hi $1", 1)]), cmd("greet you", 2)])
+        );
+        assert_eq!(
+            parse("function f\n{\n  local x=1\n  return 2\n}", 1),
+            Ok(vec![func("f", vec![cmd("local x=1", 3), cmd("return 2", 4)])])
+        );
+        assert_eq!(
+            parse("function g() { if true; then echo; fi; }", 1),
+            Ok(vec![func(
+                "g",
+                vec![Stmt::If(vec![(vec![cmd("true", 1)], vec![cmd("echo", 1)])], vec![])],
+            )])
+        );
+        assert!(is_incomplete("f() {"));
+        assert!(is_incomplete("f()"));
+        assert!(parse("f() { }", 1).is_err());
+        assert!(parse("f() echo", 1).is_err());
+        assert!(parse("echo; }", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_compound_pipelines() {
+        let for_loop = Stmt::For("i".to_string(), Some("1 2".to_string()), vec![cmd("echo $i", 1)]);
+        assert_eq!(
+            parse("for i in 1 2; do echo $i; done > out.txt", 1),
+            Ok(vec![Stmt::Redirect(Box::new(for_loop.clone()), "> out.txt".to_string())])
+        );
+        assert_eq!(
+            parse("for i in 1 2; do echo $i; done 2>&1 | sort | uniq; echo", 1),
+            Ok(vec![
+                Stmt::Pipeline(vec![
+                    Stmt::Redirect(Box::new(for_loop.clone()), "2>&1".to_string()),
+                    cmd("sort | uniq", 1),
+                ]),
+                cmd("echo", 1),
+            ])
+        );
+        assert_eq!(
+            parse("ls | sort | while true; do break; done", 1),
+            Ok(vec![Stmt::Pipeline(vec![
+                cmd("ls | sort", 1),
+                Stmt::While(vec![cmd("true", 1)], vec![cmd("break", 1)]),
+            ])])
+        );
+        assert_eq!(
+            parse("echo 'a | for' || for i in 1 2; do echo $i; done", 1),
+            Err(ParseError::Syntax(
+                "syntax error near unexpected token `do'".to_string(),
+                1
+            ))
+        );
+        assert!(is_incomplete("for i in 1 2; do echo $i; done |"));
+        assert!(parse("if true; then echo; fi | done", 1).is_err());
+        assert!(parse("if true; then echo; fi && echo", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(is_incomplete("if true; then"));
+        assert!(is_incomplete("if true; then\n if false; then echo a; fi"));
+        assert!(!is_incomplete("if true; then echo a; fi"));
+        assert!(!is_incomplete("echo 'a"));
+        assert_eq!(
+            parse("echo a\nfi", 1),
+            Err(ParseError::Syntax(
+                "syntax error near unexpected token `fi'".to_string(),
+                2
+            ))
+        );
+        assert_eq!(
+            parse("if true; then fi", 1),
+            Err(ParseError::Syntax(
+                "syntax error near unexpected token `fi'".to_string(),
+                1
+            ))
+        );
+        assert!(parse("if true; then echo; fi echo", 1).is_err());
+    }
+
+    #[test]
+    fn test_piece_ranges() {
+        let text = "echo 'a;b' \\; x; ls\n a)  ;; esac";
+        let pieces: Vec<&str> = piece_ranges(text).into_iter().map(|x| &text[x]).collect();
+        assert_eq!(pieces, vec!["echo 'a;b' \\; x", ";", " ls", "\n", " a)  ", ";;", " esac"]);
+    }
+
+    #[test]
+    fn test_parse_each() {
+        let error = |token: &str, lineno: usize| {
+            let msg = format!("syntax error near unexpected token `{}'", token);
+            Err(ParseError::Syntax(msg, lineno))
+        };
+        let text = "echo a\nfi; echo b\necho c; done\nif true; then\n  echo d\nfi\nwhile true";
+        assert_eq!(
+            parse_each(text, 1),
+            vec![
+                Ok(cmd("echo a", 1)),
+                error("fi", 2),
+                Ok(cmd("echo c", 3)),
+                error("done", 3),
+                Ok(Stmt::If(vec![(vec![cmd("true", 4)], vec![cmd("echo d", 5)])], vec![])),
+                Err(ParseError::Syntax(
+                    "syntax error: unexpected end of file".to_string(),
+                    7
+                )),
+            ]
+        );
+        assert_eq!(parse_each("\n# comment\n", 1), vec![]);
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem;
+use std::sync::atomic::Ordering;
+
+use glob;
+
+use execute;
+use parsers;
+use parsers::parser_script::ParseError;
+use shell;
+use tools;
+use types::Stmt;
+
+/// Limit of nested function calls, to stop endless recursions.
+const MAX_FUNC_DEPTH: usize = 1000;
+
+/// Whether the statements left in a list should be skipped, because of
+/// `break`, `continue`, `return` or Ctrl-C.
+fn should_stop(sh: &shell::Shell) -> bool {
+    sh.break_count > 0
+        || sh.continue_count > 0
+        || sh.returning
+        || shell::INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Run parsed statements in order, returning the status of the last one.
+pub fn run_stmts(sh: &mut shell::Shell, stmts: &[Stmt], tty: bool) -> i32 {
+    let mut status = 0;
+    for stmt in stmts {
+        status = run_stmt(sh, stmt, tty);
+        sh.previous_status = status;
+        if should_stop(sh) {
+            break;
+        }
+    }
+    status
+}
+
+/// Run the text of a script file, e.g. for `source`, statement by
+/// statement: a syntax error is reported and skips only the bad
+/// statement, see `parser_script::parse_each()`.
+pub fn run_script(sh: &mut shell::Shell, text: &str, tty: bool) -> i32 {
+    let mut status = 0;
+    for stmt in parsers::parser_script::parse_each(text, 1) {
+        status = match stmt {
+            Ok(stmt) => run_stmt(sh, &stmt, tty),
+            Err(ParseError::Syntax(msg, lineno)) => {
+                tools::set_error_location(&format!("{}:{}", sh.script_name, lineno));
+                println_stderr!("cicada: {}", msg);
+                2
+            }
+            Err(ParseError::Incomplete) => 2,
+        };
+        sh.previous_status = status;
+        if should_stop(sh) {
+            break;
+        }
+    }
+    status
+}
+
+fn run_stmt(sh: &mut shell::Shell, stmt: &Stmt, tty: bool) -> i32 {
+    match *stmt {
+        Stmt::Cmd(ref line, lineno) => {
+            sh.lineno = lineno;
+            // errors in scripts (e.g. RC files) point to their lines
+            if !sh.script_name.is_empty() {
+                tools::set_error_location(&format!("{}:{}", sh.script_name, lineno));
+            }
+            execute::run_cmd_list(sh, line, tty)
+        }
+        Stmt::If(ref branches, ref else_body) => {
+            for (cond, body) in branches {
+                if run_stmts(sh, cond, tty) == 0 {
+                    return run_stmts(sh, body, tty);
+                }
+                if should_stop(sh) {
+                    return sh.previous_status;
+                }
+            }
+            // like other shells, `if` without a taken branch succeeds
+            run_stmts(sh, else_body, tty)
+        }
+        Stmt::For(ref name, ref words, ref body) => {
+            let words = match *words {
+                Some(ref text) => expand_words(sh, text),
+                None => sh.args.clone(),
+            };
+            sh.loop_depth += 1;
+            let mut status = 0;
+            for word in words {
+                if let Err(e) = sh.set_env(name, &word) {
+                    println_stderr!("cicada: {}", e);
+                    status = 1;
+                    break;
+                }
+                status = run_stmts(sh, body, tty);
+                if end_of_loop(sh) {
+                    break;
+                }
+            }
+            sh.loop_depth -= 1;
+            status
+        }
+        Stmt::While(ref cond, ref body) => run_while(sh, cond, body, false, tty),
+        Stmt::Until(ref cond, ref body) => run_while(sh, cond, body, true, tty),
+        Stmt::Case(ref word, ref arms) => {
+            let word = expand_case_word(sh, word, false);
+            for (patterns, body) in arms {
+                for pattern in patterns {
+                    let pattern = expand_case_word(sh, pattern, true);
+                    let matched = match glob::Pattern::new(&pattern) {
+                        Ok(p) => p.matches(&word),
+                        Err(_) => pattern == word,
+                    };
+                    if matched {
+                        return run_stmts(sh, body, tty);
+                    }
+                }
+            }
+            0
+        }
+        Stmt::Func(ref name, ref body) => {
+            sh.set_func(name, body);
+            0
+        }
+        Stmt::Redirect(ref stmt, ref redirects) => {
+            execute::run_redirected(sh, redirects, |sh| run_stmt(sh, stmt, tty))
+        }
+        Stmt::Pipeline(ref parts) => {
+            execute::run_forked_pipeline(sh, parts.len(), |sh, i| run_stmt(sh, &parts[i], false))
+        }
+    }
+}
+
+/// Call a user defined function with its positional parameters.
+pub fn run_func(sh: &mut shell::Shell, name: &str, args: Vec<String>, tty: bool) -> i32 {
+    let body = match sh.get_func(name) {
+        Some(x) => x,
+        None => return 127,
+    };
+    if sh.local_frames.len() >= MAX_FUNC_DEPTH {
+        println_stderr!("cicada: {}: maximum function nesting level exceeded", name);
+        return 1;
+    }
+    let saved_args = mem::replace(&mut sh.args, args);
+    // `break` in a function does not leave loops of its caller
+    let saved_loop_depth = mem::replace(&mut sh.loop_depth, 0);
+    sh.local_frames.push(HashMap::new());
+
+    let status = run_stmts(sh, &body, tty);
+    sh.returning = false;
+
+    if let Some(frame) = sh.local_frames.pop() {
+        for (name, value) in frame {
+            let _ = match value {
+                Some(x) => sh.set_env(&name, &x),
+                None => sh.remove_env(&name),
+            };
+        }
+    }
+    sh.loop_depth = saved_loop_depth;
+    sh.args = saved_args;
+    status
+}
+
+/// Run the hook `name` (e.g. `precmd`): the function of that name if
+/// it's defined, then the command line in the variable of that name in
+/// upper case (e.g. `$PRECMD`) if it's set, both with `args` as `$1`...
+/// Hooks do not change `$?`, and no hooks are run from inside a hook.
+pub fn run_hook(sh: &mut shell::Shell, name: &str, args: Vec<String>) {
+    if sh.in_hook {
+        return;
+    }
+    let has_func = sh.funcs.contains_key(name);
+    let cmd = sh.get_env(&name.to_uppercase()).unwrap_or_default();
+    if !has_func && cmd.trim().is_empty() {
+        return;
+    }
+    let status = sh.previous_status;
+    let tty = sh.interactive;
+    sh.in_hook = true;
+    shell::INTERRUPTED.store(false, Ordering::SeqCst);
+    if has_func {
+        run_func(sh, name, args.clone(), tty);
+    }
+    if !cmd.trim().is_empty() {
+        let saved_args = mem::replace(&mut sh.args, args);
+        execute::run_procs(sh, &cmd, tty);
+        sh.args = saved_args;
+    }
+    sh.in_hook = false;
+    sh.previous_status = status;
+}
+
+fn run_while(sh: &mut shell::Shell, cond: &[Stmt], body: &[Stmt], until: bool, tty: bool) -> i32 {
+    sh.loop_depth += 1;
+    let mut status = 0;
+    loop {
+        let cond_status = run_stmts(sh, cond, tty);
+        if end_of_loop(sh) || (cond_status == 0) == until {
+            break;
+        }
+        status = run_stmts(sh, body, tty);
+        if end_of_loop(sh) {
+            break;
+        }
+    }
+    sh.loop_depth -= 1;
+    status
+}
+
+/// Handle a pending `break` or `continue` after the body of a loop ran,
+/// and tell whether the loop should end.
+fn end_of_loop(sh: &mut shell::Shell) -> bool {
+    if sh.returning || shell::INTERRUPTED.load(Ordering::SeqCst) {
+        return true;
+    }
+    if sh.break_count > 0 {
+        sh.break_count -= 1;
+        return true;
+    }
+    if sh.continue_count > 0 {
+        sh.continue_count -= 1;
+        // `continue 2` ends this loop and continues the outer one
+        return sh.continue_count > 0;
+    }
+    false
+}
+
+/// Expand the word or a pattern of `case`. Quoted parts of patterns
+/// are matched literally.
+fn expand_case_word(sh: &shell::Shell, text: &str, is_pattern: bool) -> String {
+    let mut line = text.to_string();
+    tools::expand_word(sh, &mut line);
+    let tokens = parsers::parser_line::cmd_to_tokens(&line);
+    if !is_pattern {
+        return parsers::parser_line::tokens_to_args(&tokens).join(" ");
+    }
+    let mut result = String::new();
+    for (sep, token) in tokens {
+        if sep.is_empty() {
+            result.push_str(&token);
+        } else {
+            result.push_str(&glob::Pattern::escape(&token));
+        }
+    }
+    result
+}
+
+/// Expand the words of `for NAME in WORDS` the same way as command
+/// arguments, including globs, variables and command substitutions.
+/// Results of unquoted substitutions are split on whitespace, e.g. for
+/// `for i in $(seq 3)`, while quoted words are kept whole.
+fn expand_words(sh: &shell::Shell, text: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    for (sep, word) in group_words(text) {
+        if sep == "'" {
+            result.push(word);
+            continue;
+        }
+        let mut line = word.clone();
+        tools::pre_handle_cmd_line(sh, &mut line);
+        let split = sep != "\"" && (sep == "`" || word.contains('$'));
+        for token in parsers::parser_line::line_to_plain_tokens(&line) {
+            if split {
+                result.extend(token.split_whitespace().map(|x| x.to_string()));
+            } else {
+                result.push(token);
+            }
+        }
+    }
+    result
+}
+
+/// Tokens of `text` with their separators, the ones of a `$(...)`
+/// joined back into one word, ready for `pre_handle_cmd_line()`;
+/// single quoted ones are kept unquoted.
+fn group_words(text: &str) -> Vec<(String, String)> {
+    let mut result: Vec<(String, String)> = Vec::new();
+    let mut depth = 0;
+    for (sep, token) in parsers::parser_line::cmd_to_tokens(text) {
+        let word = if sep == "'" {
+            token.clone()
+        } else {
+            tools::wrap_sep_string(&sep, &token)
+        };
+        if depth > 0 {
+            if let Some(last) = result.last_mut() {
+                last.1.push(' ');
+                last.1.push_str(&word);
+            }
+        } else {
+            result.push((sep.clone(), word));
+        }
+        if sep.is_empty() {
+            depth += token.matches('(').count() as i32 - token.matches(')').count() as i32;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::run_hook;
+    use execute::run_procs;
+    use shell::Shell;
+
+    #[test]
+    fn test_run_func() {
+        let mut sh = Shell::new();
+        let script = "x=1\nf() {\n  local x=$1\n  y=$x$#\n  return 4\n  y=no\n}\nf 2 3";
+        assert_eq!(run_procs(&mut sh, script, false), 4);
+        assert_eq!(sh.get_env("x"), Some("1".to_string()));
+        assert_eq!(sh.get_env("y"), Some("22".to_string()));
+        assert!(sh.local_frames.is_empty());
+        assert!(sh.args.is_empty());
+
+        let script = "g() { for i in 1 2 3; do z=$i; if test $i = 2; then break; fi; done; }; g";
+        assert_eq!(run_procs(&mut sh, script, false), 0);
+        assert_eq!(sh.get_env("z"), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut sh = Shell::new();
+        let script = "n=0; for i in 1 'a b' 2; do n=$n$i; done";
+        assert_eq!(run_procs(&mut sh, script, false), 0);
+        assert_eq!(sh.get_env("n"), Some("01a b2".to_string()));
+
+        let script = "X=\"a b  c\"; n=0; for i in $X; do n=$n-$i; done";
+        assert_eq!(run_procs(&mut sh, script, false), 0);
+        assert_eq!(sh.get_env("n"), Some("0-a-b-c".to_string()));
+
+        let script = "n=0; for i in \"$X\" x; do n=$n-$i; done";
+        assert_eq!(run_procs(&mut sh, script, false), 0);
+        assert_eq!(sh.get_env("n"), Some("0-a b  c-x".to_string()));
+
+        let script = "n=0; for i in $(seq 3) `echo 4 5`; do n=$n$i; done";
+        assert_eq!(run_procs(&mut sh, script, false), 0);
+        assert_eq!(sh.get_env("n"), Some("012345".to_string()));
+
+        let script = "n=0; for i in \"$(echo 1 2)\"; do n=$n-$i; done";
+        assert_eq!(run_procs(&mut sh, script, false), 0);
+        assert_eq!(sh.get_env("n"), Some("0-1 2".to_string()));
+    }
+
+    #[test]
+    fn test_run_func_redirected() {
+        let dir = env::temp_dir();
+        let out = dir.join("cicada-test-func-redirect.txt");
+        let out = out.to_string_lossy();
+        let mut sh = Shell::new();
+        let script = format!("f() {{ echo a; echo b; x=1; }}\nf > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "a\nb\n");
+        // redirected functions still run in the shell
+        assert_eq!(sh.get_env("x"), Some("1".to_string()));
+
+        let script = format!("f 2>/dev/null >> {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "a\nb\na\nb\n");
+        let _ = fs::remove_file(&*out);
+    }
+
+    #[test]
+    fn test_run_func_piped() {
+        let dir = env::temp_dir();
+        let out = dir.join("cicada-test-func-pipe.txt");
+        let out = out.to_string_lossy();
+        let mut sh = Shell::new();
+        let script = format!("f() {{ echo a; echo b; }}\nf | wc -l > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap().trim(), "2");
+
+        let script = format!("echo x | f | tr a-z A-Z > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "A\nB\n");
+        assert_eq!(run_procs(&mut sh, "f | false", false), 1);
+        let _ = fs::remove_file(&*out);
+    }
+
+    #[test]
+    fn test_run_compound_redirected() {
+        let dir = env::temp_dir();
+        let out = dir.join("cicada-test-loop-redirect.txt");
+        let out = out.to_string_lossy();
+        let mut sh = Shell::new();
+        let script = format!("for i in 1 2; do echo $i; x=$i; done > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "1\n2\n");
+        assert_eq!(sh.get_env("x"), Some("2".to_string()));
+
+        let script = format!("if true; then echo a; fi >> {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "1\n2\na\n");
+        let _ = fs::remove_file(&*out);
+    }
+
+    #[test]
+    fn test_run_compound_piped() {
+        let dir = env::temp_dir();
+        let out = dir.join("cicada-test-loop-pipe.txt");
+        let out = out.to_string_lossy();
+        let mut sh = Shell::new();
+        let script = format!("echo a | while true; do tr a b; break; done > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "b\n");
+
+        let script = format!("for i in 3 1 2; do echo $i; done | sort | head -2 > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "1\n2\n");
+
+        let script = format!("case x in x) echo x;; esac | tr x y > {}", out);
+        assert_eq!(run_procs(&mut sh, &script, false), 0);
+        assert_eq!(fs::read_to_string(&*out).unwrap(), "y\n");
+        let _ = fs::remove_file(&*out);
+    }
+
+    #[test]
+    fn test_run_hook() {
+        let mut sh = Shell::new();
+        let script = "preexec() { cmd=$1; false; }\nprecmd() { n=x$n; }";
+        run_procs(&mut sh, script, false);
+        sh.previous_status = 3;
+        run_hook(&mut sh, "preexec", vec!["ls -l".to_string()]);
+        assert_eq!(sh.get_env("cmd"), Some("ls -l".to_string()));
+        assert_eq!(sh.previous_status, 3);
+        run_hook(&mut sh, "precmd", Vec::new());
+        assert_eq!(sh.get_env("n"), Some("x".to_string()));
+        // no hooks from inside a hook
+        sh.in_hook = true;
+        run_hook(&mut sh, "precmd", Vec::new());
+        assert_eq!(sh.get_env("n"), Some("x".to_string()));
+        sh.in_hook = false;
+
+        // a command line in a variable, after the function
+        run_procs(&mut sh, "unset -f preexec; PRECMD='n=${n}y; false'", false);
+        run_procs(&mut sh, "PREEXEC='last=\"$1\"'", false);
+        sh.previous_status = 3;
+        run_hook(&mut sh, "precmd", Vec::new());
+        assert_eq!(sh.get_env("n"), Some("xxy".to_string()));
+        assert_eq!(sh.previous_status, 3);
+        run_hook(&mut sh, "preexec", vec!["ls -a".to_string()]);
+        assert_eq!(sh.get_env("last"), Some("ls -a".to_string()));
+        assert_eq!(sh.get_env("cmd"), Some("ls -l".to_string()));
+    }
+}
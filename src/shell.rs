@@ -1,75 +1,302 @@
 use errno::errno;
 use libc;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use glob;
+use nix::sys::signal;
 use regex::Regex;
+use time;
 
 use parsers;
 use tools::{self, clog};
+use types::Stmt;
+
+/// Set when Ctrl-C interrupts a foreground job, so that the running
+/// script (e.g. a whole loop) stops instead of only the current command.
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug, Clone)]
 pub struct Shell {
     pub alias: HashMap<String, String>,
+    /// global aliases, which are expanded in any word position
+    pub alias_global: HashMap<String, String>,
+    /// suffix aliases, e.g. `md` => `glow` makes `README.md` run
+    /// as `glow README.md`
+    pub alias_suffix: HashMap<String, String>,
+    /// shell variables; exported ones live in the process env instead,
+    /// so that child processes get exactly the exported set.
     pub envs: HashMap<String, String>,
+    pub readonly: HashSet<String>,
     pub cmd: String,
-    pub previous_dir: String,
     pub previous_cmd: String,
     pub previous_status: i32,
+    pub last_bg_pid: u32,
+    /// background processes that may still be running
+    pub bg_pids: Vec<u32>,
+    /// seconds the last command line took, for the prompt
+    pub cmd_duration: f64,
+    pub start_time: i64,
+    pub lineno: usize,
+    pub interactive: bool,
+    /// how many loops we are in, and how many of them `break [n]`
+    /// or `continue [n]` are leaving
+    pub loop_depth: usize,
+    pub break_count: usize,
+    pub continue_count: usize,
+    /// user defined functions, found before programs in PATH
+    pub funcs: HashMap<String, Vec<Stmt>>,
+    /// positional parameters, i.e. `$1`, `$2` and so on
+    pub args: Vec<String>,
+    /// values that `local` variables had before, one frame per function
+    /// call; `None` means the variable was not set
+    pub local_frames: Vec<HashMap<String, Option<String>>>,
+    /// set by `return` to skip the rest of the function or sourced file
+    pub returning: bool,
+    /// the file being run by `source`, for error messages
+    pub script_name: String,
+    /// how many `source` commands are running
+    pub source_depth: usize,
+    /// set while a hook function like `precmd` is running
+    pub in_hook: bool,
 }
 
 impl Shell {
     pub fn new() -> Shell {
+        let start_time = time::get_time().sec;
+        unsafe {
+            libc::srand((start_time as u32) ^ (libc::getpid() as u32));
+        }
         Shell {
             alias: HashMap::new(),
+            alias_global: HashMap::new(),
+            alias_suffix: HashMap::new(),
             envs: HashMap::new(),
+            readonly: HashSet::new(),
             cmd: String::new(),
-            previous_dir: String::new(),
             previous_cmd: String::new(),
             previous_status: 0,
+            last_bg_pid: 0,
+            bg_pids: Vec::new(),
+            cmd_duration: 0.0,
+            start_time,
+            lineno: 0,
+            interactive: false,
+            loop_depth: 0,
+            break_count: 0,
+            continue_count: 0,
+            funcs: HashMap::new(),
+            args: Vec::new(),
+            local_frames: Vec::new(),
+            returning: false,
+            script_name: String::new(),
+            source_depth: 0,
+            in_hook: false,
         }
     }
 
-    pub fn set_env(&mut self, name: &str, value: &str) {
-        if let Ok(_) = env::var(name) {
+    /// Assign a variable. Exported variables stay exported, others are
+    /// kept as shell variables.
+    pub fn set_env(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if self.is_readonly(name) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        if self.is_exported(name) {
             env::set_var(name, value);
         } else {
             self.envs.insert(name.to_string(), value.to_string());
         }
+        Ok(())
+    }
+
+    /// Forget background processes that have finished, and reap them.
+    pub fn reap_jobs(&mut self) {
+        self.bg_pids.retain(|pid| {
+            let mut stat: i32 = 0;
+            let ptr: *mut i32 = &mut stat;
+            unsafe { libc::waitpid(*pid as i32, ptr, libc::WNOHANG) == 0 }
+        });
     }
 
     pub fn get_env(&self, name: &str) -> Option<String> {
         match self.envs.get(name) {
-            Some(x) => {
-                Some(x.to_string())
-            }
-            None => {
-                None
+            Some(x) => Some(x.to_string()),
+            None => env::var(name).ok(),
+        }
+    }
+
+    pub fn is_exported(&self, name: &str) -> bool {
+        env::var_os(name).is_some()
+    }
+
+    pub fn is_readonly(&self, name: &str) -> bool {
+        self.readonly.contains(name)
+    }
+
+    /// Assign a variable and mark it as exported.
+    pub fn export_env(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if self.is_readonly(name) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        self.envs.remove(name);
+        env::set_var(name, value);
+        Ok(())
+    }
+
+    /// Remove the export attribute, keeping the value as a shell variable.
+    pub fn unexport_env(&mut self, name: &str) {
+        if let Some(value) = env::var_os(name) {
+            env::remove_var(name);
+            let value = value.to_string_lossy().to_string();
+            self.envs.insert(name.to_string(), value);
+        }
+    }
+
+    pub fn remove_env(&mut self, name: &str) -> Result<(), String> {
+        if self.is_readonly(name) {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        self.envs.remove(name);
+        env::remove_var(name);
+        Ok(())
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.readonly.insert(name.to_string());
+    }
+
+    /// All variables (shell and exported), sorted by name.
+    pub fn get_vars(&self) -> Vec<(String, String)> {
+        let mut vars: HashMap<String, String> = HashMap::new();
+        for (name, value) in env::vars_os() {
+            let name = name.to_string_lossy().to_string();
+            let value = value.to_string_lossy().to_string();
+            vars.insert(name, value);
+        }
+        for (name, value) in &self.envs {
+            vars.insert(name.clone(), value.clone());
+        }
+        let mut result: Vec<(String, String)> = vars.into_iter().collect();
+        result.sort();
+        result
+    }
+
+    /// Assign a variable local to the current function call, whose value
+    /// is restored when the function returns.
+    pub fn set_local(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if self.is_readonly(name) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        let old = self.get_env(name);
+        match self.local_frames.last_mut() {
+            Some(frame) => {
+                frame.entry(name.to_string()).or_insert(old);
             }
+            None => return Err(String::from("can only be used in a function")),
         }
+        self.set_env(name, value)
+    }
+
+    pub fn set_func(&mut self, name: &str, body: &[Stmt]) {
+        self.funcs.insert(name.to_string(), body.to_vec());
+    }
+
+    pub fn get_func(&self, name: &str) -> Option<Vec<Stmt>> {
+        self.funcs.get(name).cloned()
+    }
+
+    pub fn remove_func(&mut self, name: &str) -> bool {
+        self.funcs.remove(name).is_some()
     }
 
     pub fn add_alias(&mut self, name: &str, value: &str) {
         self.alias.insert(name.to_string(), value.to_string());
     }
 
-    pub fn get_alias_content(&self, name: &str) -> Option<String> {
-        let mut result;
-        match self.alias.get(name) {
-            Some(x) => {
-                result = x.to_string();
-            }
-            None => {
-                result = String::new();
-            }
+    pub fn add_alias_global(&mut self, name: &str, value: &str) {
+        self.alias_global.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn add_alias_suffix(&mut self, name: &str, value: &str) {
+        self.alias_suffix.insert(name.to_string(), value.to_string());
+    }
+
+    /// Removes a regular or global alias.
+    pub fn remove_alias(&mut self, name: &str) -> bool {
+        let removed = self.alias.remove(name).is_some();
+        self.alias_global.remove(name).is_some() || removed
+    }
+
+    pub fn remove_alias_suffix(&mut self, name: &str) -> bool {
+        self.alias_suffix.remove(name).is_some()
+    }
+
+    /// Returns the command to open `file` with, if there is a suffix alias
+    /// matching its extension, e.g. `glow README.md`.
+    pub fn get_alias_suffix_content(&self, file: &str) -> Option<String> {
+        let pos = file.rfind('.')?;
+        let suffix = &file[pos + 1..];
+        if pos == 0 || suffix.is_empty() || suffix.contains('/') {
+            return None;
         }
+        let value = self.alias_suffix.get(suffix)?;
+        Some(format!("{} {}", value, tools::wrap_sep_string("", file)))
+    }
+
+    /// Returns the content of alias `name`, expanded recursively, and
+    /// whether it ends with a blank, in which case the next word should
+    /// also be checked for alias expansion.
+    pub fn get_alias_content(&self, name: &str) -> Option<(String, bool)> {
+        let mut seen = Vec::new();
+        let mut result = self.expand_alias(name, &mut seen)?;
+        let ends_with_blank = result.ends_with(' ') || result.ends_with('\t');
         tools::pre_handle_cmd_line(self, &mut result);
         if result.is_empty() {
             None
         } else {
-            Some(result)
+            Some((result, ends_with_blank))
+        }
+    }
+
+    /// Expand alias recursively, e.g. with `alias ll='ls -lh'` and
+    /// `alias ls='ls -G'`, `ll` would become `ls -G -lh`. An alias that
+    /// is being expanded will not be expanded again to avoid loops.
+    fn expand_alias(&self, name: &str, seen: &mut Vec<String>) -> Option<String> {
+        if seen.iter().any(|x| x == name) {
+            return None;
+        }
+        let value = self.alias.get(name)?;
+        seen.push(name.to_string());
+
+        let content = value.trim_start();
+        let (first, rest) = match content.find(char::is_whitespace) {
+            Some(idx) => content.split_at(idx),
+            None => (content, ""),
+        };
+        match self.expand_alias(first, seen) {
+            Some(x) => Some(format!("{}{}", x, rest)),
+            None => Some(value.to_string()),
+        }
+    }
+}
+
+extern "C" fn handle_sigint(_: i32) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Make Ctrl-C stop the running script instead of killing the
+/// interactive shell itself.
+pub fn catch_sigint() {
+    let sig_action = signal::SigAction::new(
+        signal::SigHandler::Handler(handle_sigint),
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+    unsafe {
+        if let Err(e) = signal::sigaction(signal::SIGINT, &sig_action) {
+            println!("sigaction error: {:?}", e);
         }
     }
 }
@@ -177,9 +404,37 @@ pub fn extend_glob(line: &mut String) {
     *line = result.join(" ");
 }
 
+/// Values of special parameters like `$?` and `$RANDOM`, which are
+/// computed on the fly instead of being stored as variables.
+pub fn get_special_param(sh: &Shell, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(sh.previous_status.to_string()),
+        "$" => Some(unsafe { libc::getpid() }.to_string()),
+        "!" => {
+            if sh.last_bg_pid == 0 {
+                Some(String::new())
+            } else {
+                Some(sh.last_bg_pid.to_string())
+            }
+        }
+        "0" => Some(env::args().next().unwrap_or_else(|| String::from("cicada"))),
+        "-" => Some(String::from(if sh.interactive { "i" } else { "" })),
+        "PPID" => Some(unsafe { libc::getppid() }.to_string()),
+        "RANDOM" => Some((unsafe { libc::rand() } % 32768).to_string()),
+        "SECONDS" => Some((time::get_time().sec - sh.start_time).to_string()),
+        "LINENO" => Some(sh.lineno.to_string()),
+        "#" => Some(sh.args.len().to_string()),
+        "@" | "*" => Some(sh.args.join(" ")),
+        _ => match name.parse::<usize>() {
+            Ok(n) if n > 0 => Some(sh.args.get(n - 1).cloned().unwrap_or_default()),
+            _ => None,
+        },
+    }
+}
+
 pub fn extend_env_blindly(sh: &Shell, token: &str) -> String {
     let re;
-    if let Ok(x) = Regex::new(r"([^\$]*)\$\{?([A-Za-z0-9\?\$_]+)\}?(.*)") {
+    if let Ok(x) = Regex::new(r"([^\$]*)\$\{?([\?\$!\-#@\*]|[A-Za-z0-9_]+)\}?(.*)") {
         re = x;
     } else {
         println!("cicada: re new error");
@@ -204,21 +459,12 @@ pub fn extend_env_blindly(sh: &Shell, token: &str) -> String {
             _head = cap[1].to_string();
             _tail = cap[3].to_string();
             let _key = cap[2].to_string();
-            if _key == "?" {
-                result.push_str(format!("{}{}", _head, sh.previous_status).as_str());
-            } else if _key == "$" {
-                unsafe {
-                    let val = libc::getpid();
-                    result.push_str(format!("{}{}", _head, val).as_str());
-                }
-            } else if let Ok(val) = env::var(&_key) {
+            if let Some(val) = get_special_param(sh, &_key) {
+                result.push_str(format!("{}{}", _head, val).as_str());
+            } else if let Some(val) = sh.get_env(&_key) {
                 result.push_str(format!("{}{}", _head, val).as_str());
             } else {
-                if let Some(val) = sh.get_env(&_key) {
-                    result.push_str(format!("{}{}", _head, val).as_str());
-                } else {
-                    result.push_str(&_head);
-                }
+                result.push_str(&_head);
             }
         }
         if _tail.is_empty() {
@@ -236,6 +482,14 @@ pub fn extend_env(sh: &Shell, line: &mut String) {
     for (sep, token) in args {
         if sep == "`" || sep == "'" {
             result.push(tools::wrap_sep_string(&sep, &token));
+        } else if sep.is_empty() && tools::re_contains(&token, r"^[a-zA-Z_][a-zA-Z0-9_]*='[^']*'$") {
+            // single quoted values of assignments, like `FOO='$HOME'`
+            result.push(token.clone());
+        } else if sep == "\"" && token == "$@" {
+            // `"$@"` keeps every positional parameter as its own word
+            for arg in &sh.args {
+                result.push(tools::wrap_sep_string(&sep, arg));
+            }
         } else {
             let _token = extend_env_blindly(sh, &token);
             result.push(tools::wrap_sep_string(&sep, &_token));
@@ -246,10 +500,12 @@ pub fn extend_env(sh: &Shell, line: &mut String) {
 
 #[cfg(test)]
 mod tests {
+    use std::env;
     use std::fs::{self, File};
 
     use super::extend_env;
     use super::extend_glob;
+    use super::get_special_param;
     use super::needs_globbing;
     use super::Shell;
 
@@ -279,6 +535,10 @@ mod tests {
         extend_env(&sh, &mut s);
         assert_eq!(s, "echo 'hi $PATH'");
 
+        let mut s = String::from("export FOO='$PATH a' BAR=$NOT_EXIST");
+        extend_env(&sh, &mut s);
+        assert_eq!(s, "export FOO='$PATH a' BAR=");
+
         let mut s = String::from("echo \'\\\'");
         extend_env(&sh, &mut s);
         assert_eq!(s, "echo \'\\\'");
@@ -300,6 +560,65 @@ mod tests {
         assert_eq!(s, "foo is  and bar is .");
     }
 
+    #[test]
+    fn test_extend_env_special_params() {
+        let mut sh = Shell::new();
+        sh.previous_status = 3;
+        sh.last_bg_pid = 1234;
+        sh.lineno = 7;
+
+        let mut s = String::from("echo $? ${?}x $!");
+        extend_env(&sh, &mut s);
+        assert_eq!(s, "echo 3 3x 1234");
+
+        let mut s = String::from("echo $0 $LINENO");
+        extend_env(&sh, &mut s);
+        assert_eq!(s, format!("echo {} 7", env::args().next().unwrap()));
+        let seconds = get_special_param(&sh, "SECONDS").unwrap();
+        assert!(seconds.parse::<i64>().unwrap() <= 1);
+
+        let mut s = String::from("echo $-");
+        sh.interactive = true;
+        extend_env(&sh, &mut s);
+        assert_eq!(s, "echo i");
+
+        sh.args = vec!["a b".to_string(), "c".to_string()];
+        let mut s = String::from("echo $# $1 ${2} $3 \"$@\"");
+        extend_env(&sh, &mut s);
+        assert_eq!(s, "echo 2 a\\ b c  \"a b\" \"c\"");
+
+        let random = get_special_param(&sh, "RANDOM").unwrap();
+        assert!(random.parse::<i32>().unwrap() < 32768);
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut sh = Shell::new();
+        assert!(sh.set_env("CICADA_TEST_VAR_1", "foo").is_ok());
+        assert_eq!(sh.get_env("CICADA_TEST_VAR_1"), Some("foo".to_string()));
+        assert!(!sh.is_exported("CICADA_TEST_VAR_1"));
+
+        assert!(sh.export_env("CICADA_TEST_VAR_1", "foo").is_ok());
+        assert!(sh.is_exported("CICADA_TEST_VAR_1"));
+        assert!(sh.set_env("CICADA_TEST_VAR_1", "bar").is_ok());
+        assert_eq!(env::var("CICADA_TEST_VAR_1"), Ok("bar".to_string()));
+        assert!(!sh.envs.contains_key("CICADA_TEST_VAR_1"));
+
+        sh.unexport_env("CICADA_TEST_VAR_1");
+        assert!(!sh.is_exported("CICADA_TEST_VAR_1"));
+        assert_eq!(sh.get_env("CICADA_TEST_VAR_1"), Some("bar".to_string()));
+
+        sh.set_readonly("CICADA_TEST_VAR_1");
+        assert!(sh.set_env("CICADA_TEST_VAR_1", "baz").is_err());
+        assert!(sh.export_env("CICADA_TEST_VAR_1", "baz").is_err());
+        assert!(sh.remove_env("CICADA_TEST_VAR_1").is_err());
+        assert_eq!(sh.get_env("CICADA_TEST_VAR_1"), Some("bar".to_string()));
+
+        assert!(sh.export_env("CICADA_TEST_VAR_2", "foo").is_ok());
+        assert!(sh.remove_env("CICADA_TEST_VAR_2").is_ok());
+        assert_eq!(sh.get_env("CICADA_TEST_VAR_2"), None);
+    }
+
     #[test]
     fn test_extend_glob() {
         let fname = "foo bar baz.txt";
@@ -1,14 +1,21 @@
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::env;
 use std::fs::OpenOptions;
-use std::io::Write;
-use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::process::Stdio;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use time;
 
 use regex::Regex;
 
 use execute;
+use history;
 use libc;
 use libs;
 use parsers;
@@ -32,18 +39,40 @@ impl CommandResult {
 }
 
 macro_rules! println_stderr {
-    ($fmt:expr) => (
-        match writeln!(&mut ::std::io::stderr(), concat!($fmt, "\n")) {
+    ($($arg:tt)*) => (
+        match writeln!(&mut ::std::io::stderr(), "{}\n", ::tools::locate_error(format!($($arg)*))) {
             Ok(_) => {}
             Err(e) => println!("write to stderr failed: {:?}", e)
         }
     );
-    ($fmt:expr, $($arg:tt)*) => (
-        match writeln!(&mut ::std::io::stderr(), concat!($fmt, "\n"), $($arg)*) {
-            Ok(_) => {}
-            Err(e) => println!("write to stderr failed: {:?}", e)
+}
+
+thread_local! {
+    /// `file:line` of the script command that is running, e.g. of the
+    /// RC file, to put into error messages.
+    static ERROR_LOCATION: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Set the `file:line` for error messages, returning the old one.
+pub fn set_error_location(location: &str) -> String {
+    ERROR_LOCATION.with(|x| mem::replace(&mut *x.borrow_mut(), location.to_string()))
+}
+
+/// Put the location of the running script command into an error message,
+/// e.g. `cicada: cd: foo: No such file or directory` ->
+/// `cicada: ~/.cicadarc:3: cd: foo: No such file or directory`, and
+/// `usage: set` -> `~/.cicadarc:3: usage: set`.
+pub fn locate_error(msg: String) -> String {
+    ERROR_LOCATION.with(|x| {
+        let location = x.borrow();
+        if location.is_empty() {
+            return msg;
         }
-    );
+        match msg.strip_prefix("cicada: ") {
+            Some(rest) => format!("cicada: {}: {}", location, rest),
+            None => format!("{}: {}", location, msg),
+        }
+    })
 }
 
 pub fn clog(s: &str) {
@@ -107,9 +136,22 @@ pub fn get_user_completer_dir() -> String {
     format!("{}/.cicada/completers", home)
 }
 
-pub fn get_rc_file() -> String {
+/// RC files in the order they are loaded: the system-wide `/etc/cicadarc`,
+/// then `$XDG_CONFIG_HOME/cicada/cicadarc`, or `~/.cicadarc` if the former
+/// does not exist.
+pub fn get_rc_files() -> Vec<String> {
     let home = get_user_home();
-    format!("{}/{}", home, ".cicadarc")
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(ref x) if !x.is_empty() => x.clone(),
+        _ => format!("{}/.config", home),
+    };
+    let xdg_rc = format!("{}/cicada/cicadarc", config_dir);
+    let user_rc = if Path::new(&xdg_rc).exists() {
+        xdg_rc
+    } else {
+        format!("{}/.cicadarc", home)
+    };
+    vec![String::from("/etc/cicadarc"), user_rc]
 }
 
 pub fn unquote(s: &str) -> String {
@@ -120,61 +162,50 @@ pub fn unquote(s: &str) -> String {
     args[0].clone()
 }
 
-pub fn is_env(line: &str) -> bool {
-    re_contains(line, r"^ *export +[a-zA-Z0-9_]+=.*$")
+/// Quote a value so that it can be read back by cicada (or sh),
+/// e.g. for outputs of `set` and `readonly -p`.
+pub fn quote_word(s: &str) -> String {
+    if !s.is_empty() && re_contains(s, r"^[a-zA-Z0-9_\./:,@%\+=-]+$") {
+        return s.to_string();
+    }
+    // cicada does not read back `'it'\''s'`, while `"it's"` is fine
+    if s.contains('\'') && !s.contains(['"', '$', '\\', '`']) {
+        return format!("\"{}\"", s);
+    }
+    format!("'{}'", s.replace("'", "'\\''"))
 }
 
 fn should_extend_brace(line: &str) -> bool {
     re_contains(line, r"\{.*,.*\}")
 }
 
-#[allow(trivial_regex)]
-pub fn extend_bandband(sh: &shell::Shell, line: &mut String) {
-    if !re_contains(line, r"!!") {
-        return;
-    }
-    if sh.previous_cmd.is_empty() {
-        return;
-    }
-
-    let re;
-    match Regex::new(r"!!") {
-        Ok(x) => {
-            re = x;
-        }
-        Err(e) => {
-            println_stderr!("Regex new: {:?}", e);
-            return;
-        }
+/// History expansion, e.g. `!!`, `!42`, `!-2`, `!git`, `!$` and `^old^new`.
+/// Returns false if the line should not be run.
+pub fn extend_bandband(sh: &shell::Shell, line: &mut String) -> bool {
+    if !line.contains('!') && !line.starts_with('^') {
+        return true;
     }
 
-    let mut replaced = false;
-    let mut new_line = String::new();
-    let tokens = parsers::parser_line::cmd_to_tokens(line);
-    for (sep, token) in tokens {
-        if !sep.is_empty() {
-            new_line.push_str(&sep);
+    let lookup = |event: &history::Event| -> Option<String> {
+        if *event == history::Event::Last && !sh.previous_cmd.is_empty() {
+            return Some(sh.previous_cmd.clone());
         }
-
-        if re_contains(&token, r"!!") && sep != "'" {
-            let line2 = token.clone();
-            let result = re.replace_all(&line2, sh.previous_cmd.as_str());
-            new_line.push_str(&result);
-            replaced = true;
-        } else {
-            new_line.push_str(&token);
+        history::search_event(event)
+    };
+    match history::expand(line, lookup) {
+        Ok((new_line, print_only)) => {
+            if new_line == *line {
+                return true;
+            }
+            *line = new_line;
+            // print full line after extending
+            println!("{}", line);
+            !print_only
         }
-
-        if !sep.is_empty() {
-            new_line.push_str(&sep);
+        Err(e) => {
+            println_stderr!("cicada: {}", e);
+            false
         }
-        new_line.push(' ');
-    }
-
-    *line = new_line.trim_right().to_string();
-    // print full line after extending
-    if replaced {
-        println!("{}", line);
     }
 }
 
@@ -214,8 +245,8 @@ pub fn wrap_sep_string(sep: &str, s: &str) -> String {
     let mut previous_subsep = 'N';
     for c in s.chars() {
         // handle cmds like: export DIR=`brew --prefix openssl`/include
-        // or like: export foo="hello world"
-        if sep.is_empty() && (c == '`' || c == '"') {
+        // or like: export foo="hello world" bar='hello world'
+        if sep.is_empty() && (c == '`' || c == '"' || c == '\'') {
             if !met_subsep {
                 met_subsep = true;
                 previous_subsep = c;
@@ -270,7 +301,7 @@ fn do_command_substitution_for_dollar(line: &mut String) {
         }
 
         let _args = parsers::parser_line::cmd_to_tokens(&cmd);
-        let (_, _, output) = execute::run_pipeline(_args, "", false, false, true, false, None);
+        let (_, _, output, _) = execute::run_pipeline(_args, "", false, false, true, false, None);
         let _stdout;
         let output_txt;
         if let Some(x) = output {
@@ -310,7 +341,7 @@ fn do_command_substitution_for_dot(line: &mut String) {
     for (sep, token) in tokens {
         if sep == "`" {
             let _args = parsers::parser_line::cmd_to_tokens(token.as_str());
-            let (_, _, output) = execute::run_pipeline(_args, "", false, false, true, false, None);
+            let (_, _, output, _) = execute::run_pipeline(_args, "", false, false, true, false, None);
             if let Some(x) = output {
                 match String::from_utf8(x.stdout) {
                     Ok(stdout) => {
@@ -354,7 +385,7 @@ fn do_command_substitution_for_dot(line: &mut String) {
                     _head = cap[1].to_string();
                     _tail = cap[3].to_string();
                     let _args = parsers::parser_line::cmd_to_tokens(&cap[2]);
-                    let (_, _, output) =
+                    let (_, _, output, _) =
                         execute::run_pipeline(_args, "", false, false, true, false, None);
                     if let Some(x) = output {
                         match String::from_utf8(x.stdout) {
@@ -451,6 +482,14 @@ pub fn pre_handle_cmd_line(sh: &shell::Shell, line: &mut String) {
     do_command_substitution(line);
 }
 
+/// Expand a word like `pre_handle_cmd_line()` does, but without brace
+/// expansion and globbing, e.g. for the word and patterns of `case`.
+pub fn expand_word(sh: &shell::Shell, line: &mut String) {
+    extend_home(line);
+    shell::extend_env(sh, line);
+    do_command_substitution(line);
+}
+
 pub fn env_args_to_command_line() -> String {
     let mut result = String::new();
     let env_args = env::args();
@@ -466,8 +505,37 @@ pub fn env_args_to_command_line() -> String {
     result
 }
 
-pub fn is_alias(line: &str) -> bool {
-    re_contains(line, r"^ *alias +[a-zA-Z0-9_\.-]+=.*$")
+/// Run `cmd` and return its output if it succeeds within `timeout`
+/// milliseconds, otherwise kill it.
+pub fn run_with_timeout(cmd: &mut Command, timeout: u64) -> Option<String> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdout = child.stdout.take()?;
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        let _ = tx.send(output);
+    });
+    match rx.recv_timeout(Duration::from_millis(timeout)) {
+        Ok(output) => {
+            let ok = child.wait().map(|x| x.success()).unwrap_or(false);
+            if ok {
+                Some(output)
+            } else {
+                None
+            }
+        }
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            None
+        }
+    }
 }
 
 extern "C" {
@@ -538,6 +606,9 @@ pub fn extend_alias(sh: &shell::Shell, line: &str) -> String {
         }
 
         let tokens = parsers::parser_line::cmd_to_tokens(cmd);
+        // do not expand global aliases in the arguments of `alias`
+        // and `unalias` themselves
+        let no_global = !tokens.is_empty() && (tokens[0].1 == "alias" || tokens[0].1 == "unalias");
         let mut is_cmd = false;
         for (i, token) in tokens.iter().enumerate() {
             let sep = &token.0;
@@ -560,30 +631,48 @@ pub fn extend_alias(sh: &shell::Shell, line: &str) -> String {
             }
             if !is_cmd {
                 result.push(' ');
-                result.push_str(&wrap_sep_string(&sep, &arg));
+                match sh.alias_global.get(arg) {
+                    Some(value) if !no_global => result.push_str(value),
+                    _ => result.push_str(&wrap_sep_string(&sep, &arg)),
+                }
                 continue;
             }
 
             let extended;
             match sh.get_alias_content(arg) {
-                Some(_extended) => {
+                Some((_extended, ends_with_blank)) => {
                     extended = _extended;
+                    // like bash, an alias ending with a blank makes the
+                    // next word be checked for alias too, e.g. `sudo `
+                    is_cmd = ends_with_blank;
                 }
                 None => {
-                    extended = arg.clone();
+                    extended = if let Some(value) = sh.alias_global.get(arg) {
+                        value.clone()
+                    } else if let Some(value) = sh.get_alias_suffix_content(arg) {
+                        value
+                    } else {
+                        arg.clone()
+                    };
+                    is_cmd = false;
                 }
             }
             if i > 0 {
                 result.push(' ');
             }
             result.push_str(&extended);
-            is_cmd = false;
         }
     }
     result
 }
 
 pub fn create_fd_from_file(file_name: &str, append: bool) -> Result<Stdio, String> {
+    let fd = create_raw_fd_from_file(file_name, append)?;
+    let file_out = unsafe { Stdio::from_raw_fd(fd) };
+    Ok(file_out)
+}
+
+pub fn create_raw_fd_from_file(file_name: &str, append: bool) -> Result<RawFd, String> {
     let mut oos = OpenOptions::new();
     if append {
         oos.append(true);
@@ -592,11 +681,7 @@ pub fn create_fd_from_file(file_name: &str, append: bool) -> Result<Stdio, Strin
         oos.truncate(true);
     }
     match oos.create(true).open(file_name) {
-        Ok(x) => {
-            let fd = x.into_raw_fd();
-            let file_out = unsafe { Stdio::from_raw_fd(fd) };
-            Ok(file_out)
-        }
+        Ok(x) => Ok(x.into_raw_fd()),
         Err(e) => Err(format!("failed to create fd from file: {:?}", e)),
     }
 }
@@ -607,8 +692,10 @@ mod tests {
     use super::do_command_substitution;
     use super::extend_alias;
     use super::extend_bandband;
-    use super::is_alias;
+    use super::locate_error;
     use super::needs_extend_home;
+    use super::quote_word;
+    use super::set_error_location;
     use super::should_do_dollar_command_extension;
     use super::should_do_dot_command_extension;
     use shell;
@@ -627,8 +714,27 @@ mod tests {
     }
 
     #[test]
-    fn test_is_alias() {
-        assert!(is_alias("alias ls='ls -lh'"));
+    fn test_locate_error() {
+        let msg = || String::from("cicada: cd: foo: No such file or directory");
+        assert_eq!(locate_error(msg()), msg());
+        set_error_location("/tmp/rc:3");
+        assert_eq!(
+            locate_error(msg()),
+            "cicada: /tmp/rc:3: cd: foo: No such file or directory"
+        );
+        assert_eq!(locate_error("usage: set".to_string()), "/tmp/rc:3: usage: set");
+        set_error_location("");
+        assert_eq!(locate_error(msg()), msg());
+    }
+
+    #[test]
+    fn test_quote_word() {
+        assert_eq!(quote_word("foo"), "foo");
+        assert_eq!(quote_word("/usr/bin:/bin"), "/usr/bin:/bin");
+        assert_eq!(quote_word(""), "''");
+        assert_eq!(quote_word("a b"), "'a b'");
+        assert_eq!(quote_word("it's"), "\"it's\"");
+        assert_eq!(quote_word("it's $x"), "'it'\\''s $x'");
     }
 
     #[test]
@@ -663,6 +769,10 @@ mod tests {
         s = String::from("ls $(echo yoo) foo $(echo hoo)");
         do_command_substitution(&mut s);
         assert_eq!(s, "ls yoo foo hoo");
+
+        s = String::from("export FOO='a b' BAR=$(echo x)");
+        do_command_substitution(&mut s);
+        assert_eq!(s, "export FOO='a b' BAR=x");
     }
 
     #[test]
@@ -734,6 +844,51 @@ mod tests {
         assert_eq!(extend_alias(&sh, "ls a\\.b"), "ls -G a.b");
     }
 
+    #[test]
+    fn test_extend_alias_recursively() {
+        let mut sh = shell::Shell::new();
+        sh.add_alias("ls", "ls -G");
+        sh.add_alias("ll", "ls -lh");
+        sh.add_alias("l", "ll -a");
+        sh.add_alias("foo", "bar");
+        sh.add_alias("bar", "foo -x");
+        sh.add_alias("sudo", "sudo ");
+        sh.add_alias("please", "sudo");
+
+        assert_eq!(extend_alias(&sh, "ll"), "ls -G -lh");
+        assert_eq!(extend_alias(&sh, "l /tmp"), "ls -G -lh -a /tmp");
+        assert_eq!(extend_alias(&sh, "foo"), "foo -x");
+        assert_eq!(extend_alias(&sh, "bar"), "bar -x");
+
+        assert_eq!(extend_alias(&sh, "sudo ll /root"), "sudo ls -G -lh /root");
+        assert_eq!(extend_alias(&sh, "please ll"), "sudo ls -G -lh");
+        assert_eq!(extend_alias(&sh, "sudo -u ll"), "sudo -u ll");
+        assert_eq!(extend_alias(&sh, "echo ll"), "echo ll");
+    }
+
+    #[test]
+    fn test_extend_alias_global_and_suffix() {
+        let mut sh = shell::Shell::new();
+        sh.add_alias("ls", "ls -G");
+        sh.add_alias_global("G", "| grep");
+        sh.add_alias_global("NUL", "> /dev/null 2>&1");
+        sh.add_alias_suffix("md", "glow");
+        sh.add_alias_suffix("py", "python3 -u");
+
+        assert_eq!(extend_alias(&sh, "ls G foo"), "ls -G | grep foo");
+        assert_eq!(extend_alias(&sh, "make NUL && ls"), "make > /dev/null 2>&1 && ls -G");
+        assert_eq!(extend_alias(&sh, "echo 'G'"), "echo 'G'");
+        assert_eq!(extend_alias(&sh, "echo GG"), "echo GG");
+
+        assert_eq!(extend_alias(&sh, "README.md"), "glow README.md");
+        assert_eq!(extend_alias(&sh, "docs/a\\ b.md -p"), "glow docs/a\\ b.md -p");
+        assert_eq!(extend_alias(&sh, "./foo.py --help"), "python3 -u ./foo.py --help");
+        assert_eq!(extend_alias(&sh, "cat README.md"), "cat README.md");
+        assert_eq!(extend_alias(&sh, "foo.txt"), "foo.txt");
+        assert_eq!(extend_alias(&sh, ".md"), ".md");
+        assert_eq!(extend_alias(&sh, "unalias G"), "unalias G");
+    }
+
     #[test]
     fn test_extend_bandband() {
         let mut sh = shell::Shell::new();
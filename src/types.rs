@@ -6,3 +6,30 @@ pub struct Command {
     pub tokens: Tokens,
     pub redirects: Vec<Redirection>,
 }
+
+/// A statement of a script, as parsed by `parsers::parser_script`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// A command line like `ls | wc -l && echo done`, with its line number.
+    Cmd(String, usize),
+    /// The (condition, body) pairs of `if` and `elif`, and the `else` body.
+    If(Vec<(Vec<Stmt>, Vec<Stmt>)>, Vec<Stmt>),
+    /// `for NAME in WORDS`: the variable name, the unexpanded words
+    /// (`None` without `in`), and the body.
+    For(String, Option<String>, Vec<Stmt>),
+    /// `while` loop with its condition and body.
+    While(Vec<Stmt>, Vec<Stmt>),
+    /// `until` loop with its condition and body.
+    Until(Vec<Stmt>, Vec<Stmt>),
+    /// `case WORD in`: the unexpanded word, and the arms of patterns
+    /// and bodies.
+    Case(String, Vec<(Vec<String>, Vec<Stmt>)>),
+    /// Function definition with its name and body.
+    Func(String, Vec<Stmt>),
+    /// A compound command with the redirections after it, e.g.
+    /// `for ... done > out.txt`.
+    Redirect(Box<Stmt>, String),
+    /// A pipeline with compound commands in it, e.g. `ls | while read f;
+    /// do ...; done`; its other parts are `Cmd`s.
+    Pipeline(Vec<Stmt>),
+}